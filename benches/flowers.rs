@@ -0,0 +1,50 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use image::io::Reader as ImageReader;
+use image::Rgba;
+use std::sync::Arc;
+use wfc::model::Model;
+use wfc::patterns::{self, Compatibility, Symmetry};
+use wfc::retry::derive_seed;
+use wfc::tiles::Tile;
+use wfc::{Periodicity, Wave};
+
+const N: u32 = 3;
+const CELLS: usize = 48;
+
+fn tiles() -> Box<[Tile<Rgba<u8>>]> {
+    let image_data = ImageReader::open("flowers.png").unwrap().decode().unwrap();
+    patterns::extract(
+        &image_data.to_rgba8(),
+        N as usize,
+        Symmetry::ALL,
+        Periodicity::Both,
+    )
+}
+
+fn flowers(c: &mut Criterion) {
+    let tiles = tiles();
+    let mut group = c.benchmark_group("flowers");
+    group.sample_size(10);
+
+    group.bench_function("model", |b| {
+        b.iter(|| Model::new(tiles.clone(), Compatibility::Overlapping).unwrap())
+    });
+
+    let model = Arc::new(Model::new(tiles, Compatibility::Overlapping).unwrap());
+
+    group.bench_function("new", |b| b.iter(|| model.wave(CELLS, CELLS)));
+
+    group.bench_function("run", |b| {
+        b.iter(|| {
+            Wave::run_with_retries(10, |attempt| {
+                model.wave(CELLS, CELLS).with_seed(derive_seed(0, attempt))
+            })
+            .unwrap()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, flowers);
+criterion_main!(benches);
@@ -0,0 +1,136 @@
+use crate::tiles::{RemovedTile, TileId};
+
+/// A change made to the wave which can be undone.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) enum Change {
+    /// the tile was removed from the cell
+    Removed(RemovedTile),
+    /// the removal of the tile was propagated to the neighbors of the cell
+    Propagated(RemovedTile),
+}
+
+/// A tile choosen for a cell by the wave, along with the state needed to undo
+/// it.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct Decision {
+    pub(crate) cell_index: usize,
+    pub(crate) tile: TileId,
+    pub(crate) trail_len: usize,
+    pub(crate) num_collapsed: usize,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct Backtracking {
+    pub(crate) budget: usize,
+    pub(crate) backtracks: usize,
+    pub(crate) decisions: Vec<Decision>,
+    pub(crate) trail: Vec<Change>,
+}
+
+impl Backtracking {
+    pub(crate) fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            backtracks: 0,
+            decisions: Vec::new(),
+            trail: Vec::new(),
+        }
+    }
+
+    /// Checks that the decisions and changes read from a saved wave only
+    /// refer to its cells and tiles, and that every decision can be undone.
+    #[cfg(feature = "serde")]
+    pub(crate) fn validate(&self, num_cells: usize, num_tiles: usize) -> Result<(), String> {
+        let removed = self.trail.iter().map(|change| match change {
+            Change::Removed(removed) | Change::Propagated(removed) => {
+                (removed.cell_index, removed.tile)
+            }
+        });
+        let decided = self.decisions.iter().map(|d| (d.cell_index, d.tile));
+
+        if removed
+            .chain(decided)
+            .any(|(cell_index, tile)| cell_index >= num_cells || *tile >= num_tiles)
+        {
+            return Err(
+                "the backtracking refers to a cell or tile which does not exist".to_string(),
+            );
+        }
+
+        let mut trail_len = 0;
+        for decision in self.decisions.iter() {
+            if decision.trail_len < trail_len || decision.trail_len > self.trail.len() {
+                return Err("a decision can not be undone".to_string());
+            }
+            if decision.num_collapsed > num_cells {
+                return Err(format!(
+                    "a decision collapsed {} of {} cells",
+                    decision.num_collapsed, num_cells
+                ));
+            }
+
+            trail_len = decision.trail_len;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_util::coloring;
+    use crate::WaveCollapseError;
+
+    #[test]
+    fn backtracks_out_of_contradictions() {
+        let model = coloring(3);
+
+        for _ in 0..20 {
+            let mut wave = model.wave(8, 8).with_backtracking(10_000);
+            while !wave.collapsed() {
+                wave.collapse().unwrap();
+            }
+
+            for index in 0..64 {
+                let tile = wave.choosen_tile(index).unwrap().id();
+                for (_, neighbor) in wave.neighbors(index) {
+                    assert_ne!(tile, wave.choosen_tile(neighbor).unwrap().id());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn impossible_wave_fails() {
+        let model = coloring(2);
+        let mut wave = model.wave(3, 3).with_backtracking(10_000);
+
+        let result = loop {
+            match wave.collapse() {
+                Ok(_) => assert!(!wave.collapsed()),
+                Err(err) => break err,
+            }
+        };
+
+        assert!(matches!(result, WaveCollapseError::InvalidCell(_)));
+        assert!(wave.backtracks() > 0);
+    }
+
+    #[test]
+    fn budget_is_respected() {
+        let model = coloring(2);
+        let mut wave = model.wave(3, 3).with_backtracking(0);
+
+        let result = loop {
+            if let Err(err) = wave.collapse() {
+                break err;
+            }
+        };
+
+        assert!(matches!(result, WaveCollapseError::OutOfBacktracks(_)));
+        assert_eq!(wave.backtracks(), 0);
+    }
+}
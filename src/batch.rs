@@ -0,0 +1,198 @@
+use std::fmt::{self, Display};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::model::Model;
+use crate::retry::{derive_seed, RetryReport};
+use crate::Wave;
+
+/// Collapses many independent waves of one model across threads.
+///
+/// Run `i` is seeded with `derive_seed(seed, i)` and each of its attempts
+/// with a seed derived from that in turn, so the outputs only depend on the
+/// seed and not on the number of threads or the order the runs finish in.
+#[derive(Debug)]
+pub struct Batch<Data> {
+    model: Arc<Model<Data>>,
+    runs: usize,
+    seed: u64,
+    threads: usize,
+    max_attempts: usize,
+}
+
+impl<Data: Send + Sync> Batch<Data> {
+    /// Creates a batch of `runs` waves which are each attempted once on as
+    /// many threads as are available.
+    pub fn new(model: Arc<Model<Data>>, runs: usize, seed: u64) -> Self {
+        Self {
+            model,
+            runs,
+            seed,
+            threads: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            max_attempts: 1,
+        }
+    }
+
+    /// Sets the number of threads the waves are collapsed on.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        assert!(threads > 0);
+        self.threads = threads;
+        self
+    }
+
+    /// Retries each run up to `max_attempts` times like
+    /// [`Wave::run_with_retries`].
+    pub fn with_max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Collapses the waves created by `build`, which is passed the model, and
+    /// hands every collapsed wave to `output` along with the number of its
+    /// run on the thread which collapsed it.
+    pub fn run<F, O, T>(&self, build: F, output: O) -> BatchReport<T>
+    where
+        F: Fn(&Arc<Model<Data>>) -> Wave<Data> + Sync,
+        O: Fn(usize, Wave<Data>) -> T + Sync,
+        T: Send,
+    {
+        let next = AtomicUsize::new(0);
+
+        let mut runs: Vec<_> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.threads.min(self.runs))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut finished = Vec::new();
+
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            if index >= self.runs {
+                                return finished;
+                            }
+
+                            let seed = derive_seed(self.seed, index);
+                            let result = Wave::run_with_retries(self.max_attempts, |attempt| {
+                                build(&self.model).with_seed(derive_seed(seed, attempt))
+                            })
+                            .map(|(wave, report)| (output(index, wave), report));
+
+                            finished.push(BatchRun {
+                                index,
+                                seed,
+                                result,
+                            });
+                        }
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().unwrap())
+                .collect()
+        });
+
+        runs.sort_by_key(|run| run.index);
+
+        BatchReport { runs }
+    }
+}
+
+/// A single run of a [`Batch`].
+#[derive(Debug)]
+pub struct BatchRun<T> {
+    pub index: usize,
+    /// the seed the attempts of the run are derived from
+    pub seed: u64,
+    /// what the output of the run returned, and the attempts made
+    pub result: Result<(T, RetryReport), RetryReport>,
+}
+
+/// The runs of a [`Batch`] in order.
+#[derive(Debug)]
+pub struct BatchReport<T> {
+    pub runs: Vec<BatchRun<T>>,
+}
+
+impl<T> BatchReport<T> {
+    /// The runs which failed every attempt.
+    pub fn failures(&self) -> impl Iterator<Item = &BatchRun<T>> {
+        self.runs.iter().filter(|run| run.result.is_err())
+    }
+}
+
+impl<T> Display for BatchReport<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failures = self.failures().count();
+        let failed_attempts: usize = self
+            .runs
+            .iter()
+            .map(|run| match &run.result {
+                Ok((_, report)) | Err(report) => report.failures.len(),
+            })
+            .sum();
+
+        write!(
+            f,
+            "{} of {} runs succeeded, {} attempts failed",
+            self.runs.len() - failures,
+            self.runs.len(),
+            failed_attempts
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::test_util::coloring;
+
+    fn outputs(model: &Arc<Model<usize>>, threads: usize) -> Vec<Option<Vec<usize>>> {
+        let report = Batch::new(Arc::clone(model), 12, 5)
+            .with_threads(threads)
+            .with_max_attempts(3)
+            .run(
+                |model| model.wave(6, 6),
+                |_, wave| wave.to_grid().unwrap().concat(),
+            );
+
+        assert_eq!(
+            report.runs.iter().map(|run| run.index).collect::<Vec<_>>(),
+            (0..12).collect::<Vec<_>>()
+        );
+
+        report
+            .runs
+            .into_iter()
+            .map(|run| run.result.ok().map(|(output, _)| output))
+            .collect()
+    }
+
+    #[test]
+    fn outputs_do_not_depend_on_threads() {
+        let model = coloring(3);
+        let outputs = outputs(&model, 1);
+
+        assert!(outputs.iter().any(|output| output.is_some()));
+        assert_eq!(outputs, self::outputs(&model, 4));
+        assert_eq!(outputs, self::outputs(&model, 20));
+    }
+
+    #[test]
+    fn reports_failed_runs() {
+        let model = coloring(2);
+        let report = Batch::new(model, 5, 1)
+            .with_max_attempts(2)
+            .run(|model| model.wave(3, 3), |index, _| index);
+
+        assert_eq!(report.failures().count(), 5);
+        assert_eq!(
+            report.to_string(),
+            "0 of 5 runs succeeded, 10 attempts failed"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+/// A fixed size set of tile indices stored one bit per tile.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct TileSet {
+    words: Box<[u64]>,
+}
+
+impl TileSet {
+    const BITS: usize = u64::BITS as usize;
+
+    /// a set containing every index below `len`
+    pub(crate) fn full(len: usize) -> Self {
+        let mut words = vec![u64::MAX; len.div_ceil(Self::BITS)].into_boxed_slice();
+
+        if !len.is_multiple_of(Self::BITS) {
+            if let Some(last) = words.last_mut() {
+                *last = (1 << (len % Self::BITS)) - 1;
+            }
+        }
+
+        Self { words }
+    }
+
+    pub(crate) fn contains(&self, index: usize) -> bool {
+        self.words[index / Self::BITS] & (1 << (index % Self::BITS)) != 0
+    }
+
+    /// returns true if the index was not already in the set
+    pub(crate) fn insert(&mut self, index: usize) -> bool {
+        let word = &mut self.words[index / Self::BITS];
+        let bit = 1 << (index % Self::BITS);
+        let inserted = *word & bit == 0;
+        *word |= bit;
+
+        inserted
+    }
+
+    /// returns true if the index was in the set
+    pub(crate) fn remove(&mut self, index: usize) -> bool {
+        let word = &mut self.words[index / Self::BITS];
+        let bit = 1 << (index % Self::BITS);
+        let removed = *word & bit != 0;
+        *word &= !bit;
+
+        removed
+    }
+
+    /// removes every index other than `keep` from the set, calling `removed`
+    /// with each
+    pub(crate) fn retain_only(&mut self, keep: usize, mut removed: impl FnMut(usize)) {
+        for (i, word) in self.words.iter_mut().enumerate() {
+            let keep_bit = if keep / Self::BITS == i {
+                1 << (keep % Self::BITS)
+            } else {
+                0
+            };
+            let mut removed_bits = *word & !keep_bit;
+            *word &= keep_bit;
+
+            while removed_bits != 0 {
+                removed(i * Self::BITS + removed_bits.trailing_zeros() as usize);
+                removed_bits &= removed_bits - 1;
+            }
+        }
+    }
+
+    /// whether the set has the size of [`TileSet::full`] for `len` and only
+    /// indices below `len`
+    #[cfg(feature = "serde")]
+    pub(crate) fn fits(&self, len: usize) -> bool {
+        self.words.len() == len.div_ceil(Self::BITS) && self.iter().last().is_none_or(|i| i < len)
+    }
+
+    /// the indices in the set in ascending order
+    pub(crate) fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, word)| {
+            let mut word = *word;
+
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+
+                Some(i * Self::BITS + bit)
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn full() {
+        assert_eq!(TileSet::full(0).iter().count(), 0);
+        assert_eq!(
+            TileSet::full(5).iter().collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4]
+        );
+        assert_eq!(TileSet::full(64).iter().count(), 64);
+        assert_eq!(TileSet::full(130).iter().count(), 130);
+        assert!(TileSet::full(130).contains(129));
+    }
+
+    #[test]
+    fn insert_remove() {
+        let mut set = TileSet::full(100);
+
+        assert!(set.remove(3));
+        assert!(!set.remove(3));
+        assert!(set.remove(70));
+        assert!(!set.contains(3));
+        assert!(!set.contains(70));
+        assert!(set.contains(99));
+        assert_eq!(set.iter().count(), 98);
+
+        assert!(set.insert(70));
+        assert!(!set.insert(70));
+        assert!(set.contains(70));
+        assert_eq!(set.iter().filter(|i| *i >= 64).count(), 36);
+    }
+
+    #[test]
+    fn retain_only() {
+        let mut set = TileSet::full(100);
+        set.remove(5);
+
+        let mut removed = Vec::new();
+        set.retain_only(66, |i| removed.push(i));
+
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![66]);
+        assert_eq!(removed.len(), 98);
+        assert!(!removed.contains(&5));
+        assert!(!removed.contains(&66));
+    }
+}
@@ -1,55 +1,35 @@
 use std::ops::{Index, IndexMut};
-use std::vec;
-
-use image::Pixel;
-use rand::prelude::SliceRandom;
 
+use crate::bitset::TileSet;
+use crate::model::Model;
 use crate::slots::{Location, LocationTable};
-use crate::tiles::{Tile, TileTable};
-
-enum DecrementWaysToBecomeTileResult {
-    AlreadyZero,
-    NotZero,
-    Zero,
-}
+use crate::tiles::{Tile, TileId, TileTable};
 
+/// The number of tiles in each neighboring cell which still allow the tile.
+///
+/// The counts are kept exact even once the tile has been removed from its
+/// cell, so every decrement can be undone by an increment when backtracking.
 #[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct WaysToBecomeTile {
-    location_map: LocationTable<usize>,
+    location_map: LocationTable<u16>,
 }
 
 impl WaysToBecomeTile {
-    fn is_zero(&self) -> bool {
-        // uncomment to ensure underlying contract is upheld by other methods
-        //
-        // assert_eq!(
-        //     self.location_map.iter().all(|(_, c)| *c == 0),
-        //     self.location_map[Location::North] == 0
-        // );
-        self.location_map[Location::North] == 0
+    /// returns true when the last way to support the tile from `location` was
+    /// removed
+    fn decrement(&mut self, location: Location) -> bool {
+        self.location_map[location] -= 1;
+        self.location_map[location] == 0
     }
 
-    fn clear(&mut self) {
-        self.location_map = LocationTable::default();
-    }
-
-    fn decrement(&mut self, location: Location) -> DecrementWaysToBecomeTileResult {
-        match &mut self.location_map[location] {
-            0 => DecrementWaysToBecomeTileResult::AlreadyZero,
-            1 => {
-                self.clear();
-                DecrementWaysToBecomeTileResult::Zero
-            }
-            _ => {
-                self.location_map[location] -= 1;
-                DecrementWaysToBecomeTileResult::NotZero
-            }
-        }
+    fn increment(&mut self, location: Location) {
+        self.location_map[location] += 1;
     }
 }
 
 impl Index<Location> for WaysToBecomeTile {
-    type Output = usize;
+    type Output = u16;
 
     fn index(&self, index: Location) -> &Self::Output {
         &self.location_map[index]
@@ -62,30 +42,31 @@ impl IndexMut<Location> for WaysToBecomeTile {
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct Cell<'a, Data> {
-    ways_to_become_tile: TileTable<WaysToBecomeTile>,
-    tiles: TileTable<Option<&'a Tile<Data>>>,
+/// The tiles which may still be choosen for a cell of a wave, by their ids in
+/// the model of the wave.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cell {
+    /// copied from the model the first time a neighbor loses a tile, until
+    /// then the cell shares the counts of the model
+    ways_to_become_tile: Option<TileTable<WaysToBecomeTile>>,
+    remaining_tiles: TileSet,
     num_remaining_tiles: usize,
     sum_weights: f64,
     sum_weight_log_weight: f64,
 }
 
-impl<'a, Data> Cell<'a, Data> {
-    pub(crate) fn new(
-        ways_to_become_tile: TileTable<WaysToBecomeTile>,
-        tiles: TileTable<Option<&'a Tile<Data>>>,
-    ) -> Self {
-        let probs = tiles.iter().filter_map(|o| o.map(|t| t.probability));
-        let sum_weights: f64 = probs.clone().sum();
-        let sum_weight_log_weight: f64 = probs.map(|p| p * p.log(2.0)).sum();
+impl Cell {
+    /// a cell where any of the tiles may still be choosen
+    pub(crate) fn new<Data>(model: &Model<Data>) -> Self {
+        let num_tiles = model.tiles().len();
 
         Self {
-            num_remaining_tiles: tiles.len(),
-            ways_to_become_tile,
-            tiles,
-            sum_weights,
-            sum_weight_log_weight,
+            ways_to_become_tile: None,
+            remaining_tiles: TileSet::full(num_tiles),
+            num_remaining_tiles: num_tiles,
+            sum_weights: model.sum_weights(),
+            sum_weight_log_weight: model.sum_weight_log_weight(),
         }
     }
 
@@ -98,25 +79,9 @@ impl<'a, Data> Cell<'a, Data> {
         // is upheld by other methods
         //
         // assert_eq!(
-        //     self.tiles
-        //         .iter()
-        //         .cloned()
-        //         .filter_map(|o| o)
-        //         .collect::<Box<_>>()
-        //         .len()
-        //         == 1,
+        //     self.remaining_tiles.iter().count() == 1,
         //     self.num_remaining_tiles == 1
         // );
-
-        // assert_eq!(
-        //     self.ways_to_become_tile
-        //         .iter()
-        //         .filter(|a| !a.is_zero())
-        //         .collect::<Box<_>>()
-        //         .len()
-        //         == 1,
-        //     self.num_remaining_tiles == 1,
-        // );
         self.num_remaining_tiles == 1
     }
 
@@ -124,88 +89,180 @@ impl<'a, Data> Cell<'a, Data> {
         self.num_remaining_tiles >= 2
     }
 
+    pub fn num_remaining_tiles(&self) -> usize {
+        self.num_remaining_tiles
+    }
+
     pub fn entropy(&self) -> f64 {
         self.sum_weights.log(2.0) - (self.sum_weight_log_weight / self.sum_weights)
     }
 
-    pub fn choosen_tile(&self) -> Option<&Tile<Data>> {
+    pub fn choosen_tile(&self) -> Option<TileId> {
         if self.num_remaining_tiles == 1 {
-            self.tiles.iter().cloned().find_map(|o| o)
+            self.remaining_tiles().next()
         } else {
             None
         }
     }
 
-    pub(crate) fn to_image<T: Pixel>(
-        &self,
-        blend_cell: fn(data: &Box<[&Box<[Data]>]>) -> Box<[T]>,
-    ) -> Box<[T]> {
-        let remaining_tiles = self
-            .tiles
-            .iter()
-            .filter_map(|option| option.map(|tile| tile.data()))
-            .collect();
-
-        blend_cell(&remaining_tiles)
+    /// whether the tile may still be choosen for the cell
+    pub fn is_remaining(&self, tile: TileId) -> bool {
+        self.remaining_tiles.contains(*tile)
     }
 
-    pub(crate) fn collapse<Rng: rand::Rng>(&mut self, rng: &mut Rng) -> Box<[&'a Tile<Data>]> {
-        let remaining_tiles: Box<_> = self
-            .tiles
-            .iter()
-            .cloned()
-            .filter_map(|option| option)
-            .collect();
+    /// the tiles which may still be choosen for the cell
+    pub fn remaining_tiles(&self) -> impl Iterator<Item = TileId> + '_ {
+        self.remaining_tiles.iter().map(TileId::from)
+    }
 
-        let choosen = remaining_tiles
-            .choose_weighted(rng, |t| t.probability)
-            .unwrap()
-            .clone();
+    /// picks one of the remaining tiles weighted by the tile probabilities
+    pub(crate) fn choose_tile<Data, Rng: rand::Rng>(
+        &self,
+        model: &Model<Data>,
+        rng: &mut Rng,
+    ) -> TileId {
+        let mut target = rng.gen::<f64>() * self.sum_weights;
+        let mut choosen = None;
+
+        // the sum of the weights is updated incrementally so it may be off by
+        // a rounding error, in which case the last tile is choosen
+        for tile in self.remaining_tiles() {
+            choosen = Some(tile);
+            target -= model.tile(tile).probability;
+
+            if target < 0.0 {
+                break;
+            }
+        }
 
-        // in the future consider not allocating so much leveraging remaining tiles
-        self.tiles[choosen] = None;
-        let mut removed = TileTable(vec![None; self.tiles.len()].into_boxed_slice());
-        removed[choosen] = Some(choosen);
+        choosen.expect("an uncollapsed cell has remaining tiles")
+    }
 
-        std::mem::swap(&mut self.tiles, &mut removed);
+    /// removes every tile other than `choosen`, calling `removed` with each
+    pub(crate) fn collapse<Data>(&mut self, choosen: &Tile<Data>, mut removed: impl FnMut(TileId)) {
+        self.remaining_tiles
+            .retain_only(*choosen.id, |i| removed(TileId::from(i)));
 
         self.num_remaining_tiles = 1;
-
-        let removed: Box<_> = removed.iter().cloned().filter_map(|o| o).collect();
-        removed.iter().cloned().for_each(|t| {
-            self.ways_to_become_tile[t].clear();
-            self.update_entropy_constants(t);
-        });
-
-        removed
+        self.sum_weights = choosen.probability;
+        self.sum_weight_log_weight = choosen.probability * choosen.probability.log(2.0);
     }
 
-    pub(crate) fn removed_neighbor_tile(
+    pub(crate) fn removed_neighbor_tile<Data>(
         &mut self,
-        removed: &'a Tile<Data>,
+        model: &Model<Data>,
+        removed: &Tile<Data>,
         removed_location: Location,
-    ) -> Option<&'a Tile<Data>> {
-        let temp = match self.ways_to_become_tile[removed].decrement(removed_location) {
-            DecrementWaysToBecomeTileResult::AlreadyZero => None,
-            DecrementWaysToBecomeTileResult::NotZero => None,
-            DecrementWaysToBecomeTileResult::Zero => self.remove_tile(removed),
-        };
+    ) -> Option<TileId> {
+        if self.ways_to_become_tile_mut(model)[removed].decrement(removed_location) {
+            self.remove_tile(removed)
+        } else {
+            None
+        }
+    }
 
-        temp
+    /// undoes [`Cell::removed_neighbor_tile`]
+    pub(crate) fn restored_neighbor_tile<Data>(
+        &mut self,
+        model: &Model<Data>,
+        restored: &Tile<Data>,
+        restored_location: Location,
+    ) {
+        self.ways_to_become_tile_mut(model)[restored].increment(restored_location);
     }
 
-    fn remove_tile(&mut self, removed: &'a Tile<Data>) -> Option<&'a Tile<Data>> {
-        let mut out = None;
+    fn ways_to_become_tile_mut<Data>(
+        &mut self,
+        model: &Model<Data>,
+    ) -> &mut TileTable<WaysToBecomeTile> {
+        self.ways_to_become_tile
+            .get_or_insert_with(|| model.ways_to_become_tile().clone())
+    }
+
+    /// returns the tile if it was still remaining in the cell
+    pub(crate) fn remove_tile<Data>(&mut self, removed: &Tile<Data>) -> Option<TileId> {
+        if !self.remaining_tiles.remove(*removed.id) {
+            return None;
+        }
 
-        std::mem::swap(&mut self.tiles[removed], &mut out);
         self.num_remaining_tiles -= 1;
-        self.update_entropy_constants(removed);
+        self.update_entropy_constants(removed, -1.0);
+
+        Some(removed.id)
+    }
+
+    /// undoes [`Cell::remove_tile`]
+    pub(crate) fn restore_tile<Data>(&mut self, restored: &Tile<Data>) {
+        let inserted = self.remaining_tiles.insert(*restored.id);
+        debug_assert!(inserted);
+
+        self.num_remaining_tiles += 1;
+        self.update_entropy_constants(restored, 1.0);
+    }
+
+    /// the number of tiles in the neighboring cell at `location` which still
+    /// allow the tile
+    #[cfg(feature = "serde")]
+    pub(crate) fn ways_to_become_tile<Data>(
+        &self,
+        model: &Model<Data>,
+        tile: TileId,
+        location: Location,
+    ) -> u16 {
+        let ways_to_become_tile = self.ways_to_become_tile.as_ref();
+        ways_to_become_tile.unwrap_or_else(|| model.ways_to_become_tile())[&tile][location]
+    }
+
+    /// Checks that a cell read from a saved wave has room for exactly the
+    /// tiles of its model, and that its weights are those of its remaining
+    /// tiles.
+    #[cfg(feature = "serde")]
+    pub(crate) fn validate<Data>(&self, model: &Model<Data>) -> Result<(), String> {
+        let num_tiles = model.tiles().len();
+        if !self.remaining_tiles.fits(num_tiles) {
+            return Err(format!(
+                "the remaining tiles are not a set of {} tiles",
+                num_tiles
+            ));
+        }
+        let num_remaining_tiles = self.remaining_tiles.iter().count();
+        if self.num_remaining_tiles != num_remaining_tiles {
+            return Err(format!(
+                "{} remaining tiles are counted as {}",
+                num_remaining_tiles, self.num_remaining_tiles
+            ));
+        }
+        if let Some(ways_to_become_tile) = &self.ways_to_become_tile {
+            if ways_to_become_tile.len() != num_tiles {
+                return Err(format!(
+                    "support is counted for {} of {} tiles",
+                    ways_to_become_tile.len(),
+                    num_tiles
+                ));
+            }
+        }
+
+        let probabilities: Vec<_> = self
+            .remaining_tiles()
+            .map(|t| model.tile(t).probability)
+            .collect();
+        let sum_weights: f64 = probabilities.iter().sum();
+        let sum_weight_log_weight: f64 = probabilities.iter().map(|p| p * p.log(2.0)).sum();
+
+        // the weights are updated incrementally, so they may be off by
+        // rounding errors
+        let close = |a: f64, b: f64| (a - b).abs() <= 1e-6 * (1.0 + b.abs());
+        if !close(self.sum_weights, sum_weights)
+            || !close(self.sum_weight_log_weight, sum_weight_log_weight)
+        {
+            return Err("the weights do not match the remaining tiles".to_string());
+        }
 
-        out
+        Ok(())
     }
 
-    fn update_entropy_constants(&mut self, tile: &Tile<Data>) {
-        self.sum_weights -= tile.probability;
-        self.sum_weight_log_weight -= tile.probability * tile.probability.log(2.0);
+    fn update_entropy_constants<Data>(&mut self, tile: &Tile<Data>, sign: f64) {
+        self.sum_weights += sign * tile.probability;
+        self.sum_weight_log_weight += sign * tile.probability * tile.probability.log(2.0);
     }
 }
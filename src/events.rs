@@ -0,0 +1,181 @@
+use std::fmt::{self, Debug};
+
+use rand::Rng;
+
+use crate::tiles::TileId;
+use crate::{Wave, WaveCollapseError};
+
+/// Something which happened to the cells of a wave, see [`Wave::with_listener`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The cell was collapsed to the tile, by the wave or [`Wave::observe`].
+    Observed { cell_index: usize, tile: TileId },
+    /// The tile was removed from the cell.
+    Banned { cell_index: usize, tile: TileId },
+    /// The tile was put back into the cell when backtracking.
+    Restored { cell_index: usize, tile: TileId },
+    /// The cell was left without any tiles.
+    Contradiction { cell_index: usize },
+}
+
+/// What happened while the wave observed a single cell, returned by
+/// [`Wave::collapse`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    /// the cell which was observed
+    pub cell_index: usize,
+    /// the tile choosen for the cell, which is banned from it again if
+    /// backtracking undid the choice
+    pub tile: TileId,
+    /// the number of tiles removed from any cell, including the tiles
+    /// removed from the observed cell itself
+    pub bans: usize,
+    /// the number of cells collapsed by the step, zero if backtracking undid
+    /// more than that
+    pub newly_collapsed: usize,
+    /// the number of choices undone to recover from contradictions
+    pub backtracks: usize,
+}
+
+/// Iterates over the steps of collapsing a wave, see [`Wave::steps`].
+#[derive(Debug)]
+pub struct Steps<'w, Data, R> {
+    wave: &'w mut Wave<Data, R>,
+    failed: bool,
+}
+
+impl<'w, Data, R> Steps<'w, Data, R> {
+    pub(crate) fn new(wave: &'w mut Wave<Data, R>) -> Self {
+        Self {
+            wave,
+            failed: false,
+        }
+    }
+}
+
+impl<'w, Data, R: Rng> Iterator for Steps<'w, Data, R> {
+    type Item = Result<Step, WaveCollapseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.wave.collapsed() {
+            return None;
+        }
+
+        match self.wave.collapse() {
+            Ok(step) => step.map(Ok),
+            Err(error) => {
+                self.failed = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+type Listener = Box<dyn FnMut(&Event) + Send>;
+
+/// Counts the bans of a wave and passes its events on to the listener.
+#[derive(Default)]
+pub(crate) struct Events {
+    listener: Option<Listener>,
+    pub(crate) bans: usize,
+}
+
+impl Events {
+    pub(crate) fn new(listener: Listener) -> Self {
+        Self {
+            listener: Some(listener),
+            bans: 0,
+        }
+    }
+
+    pub(crate) fn emit(&mut self, event: Event) {
+        if let Event::Banned { .. } = event {
+            self.bans += 1;
+        }
+
+        if let Some(listener) = &mut self.listener {
+            listener(&event);
+        }
+    }
+}
+
+impl Debug for Events {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Events")
+            .field("listener", &self.listener.is_some())
+            .field("bans", &self.bans)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::test_util::coloring;
+
+    fn record<Data>(wave: Wave<Data>) -> (Wave<Data>, Arc<Mutex<Vec<Event>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+
+        (
+            wave.with_listener(move |event| recorded.lock().unwrap().push(*event)),
+            events,
+        )
+    }
+
+    #[test]
+    fn steps_match_events() {
+        // the seed collapses the wave without contradictions
+        let model = coloring(3);
+        let (mut wave, events) = record(model.wave(4, 4).with_seed(4));
+
+        let steps: Vec<_> = wave.steps().collect::<Result<_, _>>().unwrap();
+        assert!(wave.collapsed());
+
+        let events = events.lock().unwrap();
+        let observed: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                Event::Observed { cell_index, tile } => Some((*cell_index, *tile)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            observed,
+            steps
+                .iter()
+                .map(|s| (s.cell_index, s.tile))
+                .collect::<Vec<_>>()
+        );
+
+        let bans = events
+            .iter()
+            .filter(|e| matches!(e, Event::Banned { .. }))
+            .count();
+        assert_eq!(bans, steps.iter().map(|s| s.bans).sum::<usize>());
+        // every cell had three tiles and is left with one
+        assert_eq!(bans, 16 * 2);
+        assert_eq!(steps.iter().map(|s| s.newly_collapsed).sum::<usize>(), 16);
+    }
+
+    #[test]
+    fn contradictions_are_emitted() {
+        let model = coloring(2);
+        let (mut wave, events) = record(model.wave(3, 3));
+
+        let last = wave.steps().last().unwrap();
+        let invalid = match last {
+            Err(WaveCollapseError::InvalidCell(invalid)) => invalid,
+            other => panic!("expected a contradiction, got {:?}", other),
+        };
+
+        assert_eq!(
+            events.lock().unwrap().last(),
+            Some(&Event::Contradiction {
+                cell_index: invalid
+            })
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::patterns::{Compatibility, Grid};
+use crate::{Wave, WaveCollapseError};
+
+impl<Data, R: Rng> Wave<Data, R> {
+    /// The width and height of the data the wave produces, one element per
+    /// cell for [`Compatibility::Overlapping`] plus the rest of the patterns
+    /// of the last cells before an edge which does not wrap around, and whole
+    /// patterns next to each other otherwise.
+    pub fn grid_size(&self) -> (usize, usize) {
+        let size = self.model.size();
+
+        match self.model.compatibility() {
+            Compatibility::Edge => (self.x_cells * size, self.y_cells * size),
+            Compatibility::Overlapping => {
+                let x_border = if self.periodicity.wraps_horizontally() {
+                    0
+                } else {
+                    size - 1
+                };
+                let y_border = if self.periodicity.wraps_vertically() {
+                    0
+                } else {
+                    size - 1
+                };
+
+                (self.x_cells + x_border, self.y_cells + y_border)
+            }
+        }
+    }
+
+    /// The position in the grid of the element at column `x` and row `y` of
+    /// the pattern of the cell at `col` and `row`.
+    fn grid_position(&self, col: usize, row: usize, x: usize, y: usize) -> (usize, usize) {
+        let size = self.model.size();
+        let (width, height) = self.grid_size();
+
+        match self.model.compatibility() {
+            Compatibility::Edge => (col * size + x, row * size + y),
+            Compatibility::Overlapping => ((col + x) % width, (row + y) % height),
+        }
+    }
+
+    /// The data of a collapsed wave laid out as described by
+    /// [`Wave::grid_size`], such as to inpaint part of it with another wave.
+    pub fn to_grid(&self) -> Option<Vec<Vec<Data>>>
+    where
+        Data: Clone,
+    {
+        if !self.collapsed() {
+            return None;
+        }
+
+        let (width, height) = self.grid_size();
+        let size = self.model.size();
+        let mut grid: Vec<Vec<Option<Data>>> = vec![vec![None; width]; height];
+
+        for (index, cell) in self.cells.iter().enumerate() {
+            let data = self.model.tile(cell.choosen_tile()?).data();
+            let (col, row) = (index % self.x_cells, index / self.x_cells);
+
+            for (i, element) in data.iter().enumerate() {
+                let (x, y) = self.grid_position(col, row, i % size, i / size);
+                grid[y][x].get_or_insert_with(|| element.clone());
+            }
+        }
+
+        grid.into_iter()
+            .map(|row| row.into_iter().collect())
+            .collect()
+    }
+
+    /// Keeps the elements of `image` which are not masked, regenerating only
+    /// the masked elements when the wave is collapsed so they blend in with
+    /// their surroundings.
+    ///
+    /// `image` and `mask` are laid out as described by [`Wave::grid_size`],
+    /// every cell is left with only the tiles which agree with all of the
+    /// unmasked elements their pattern covers. Contradictions are reported
+    /// like [`Wave::observe`], such as when the unmasked elements contain a
+    /// pattern which does not occur in the tiles.
+    pub fn inpaint<G, M>(&mut self, image: &G, mask: &M) -> Result<(), WaveCollapseError>
+    where
+        G: Grid<Data = Data> + ?Sized,
+        M: Grid<Data = bool> + ?Sized,
+        Data: PartialEq,
+    {
+        let grid_size = self.grid_size();
+        assert_eq!((image.width(), image.height()), grid_size);
+        assert_eq!((mask.width(), mask.height()), grid_size);
+
+        let model = Arc::clone(&self.model);
+        let size = model.size();
+
+        self.constrain(|wave| {
+            for index in 0..wave.cells.len() {
+                let (col, row) = (index % wave.x_cells, index / wave.x_cells);
+
+                // the unmasked elements covered by the pattern of the cell
+                let known: Vec<_> = (0..size * size)
+                    .filter_map(|i| {
+                        let (x, y) = wave.grid_position(col, row, i % size, i / size);
+                        (!mask.get(x, y)).then(|| (i, image.get(x, y)))
+                    })
+                    .collect();
+
+                if known.is_empty() {
+                    continue;
+                }
+
+                for tile in model.tiles() {
+                    let disagrees = known.iter().any(|(i, data)| tile.data()[*i] != *data);
+
+                    if disagrees && wave.cells[index].is_remaining(tile.id()) {
+                        wave.remove_tile(index, tile.id())?;
+                    }
+                }
+            }
+
+            wave.propagate()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::model::Model;
+    use crate::patterns::{self, Compatibility, Symmetry};
+    use crate::test_util::chain;
+    use crate::Periodicity;
+
+    /// a mask of the rectangle from `x0`, `y0` up to `x1`, `y1`
+    fn mask(
+        width: usize,
+        height: usize,
+        (x0, y0): (usize, usize),
+        (x1, y1): (usize, usize),
+    ) -> Vec<Vec<bool>> {
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| (x0..x1).contains(&x) && (y0..y1).contains(&y))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn inpaint_keeps_unmasked_cells() {
+        let model = chain();
+
+        let mut wave = model.wave(8, 6).with_seed(1);
+        wave.run().unwrap();
+        let original = wave.to_grid().unwrap();
+        let mask = mask(8, 6, (2, 1), (6, 4));
+
+        let mut wave = model.wave(8, 6).with_seed(2);
+        wave.inpaint(&original, &mask).unwrap();
+        wave.run().unwrap();
+        let inpainted = wave.to_grid().unwrap();
+
+        for y in 0..6 {
+            for x in 0..8 {
+                if !mask[y][x] {
+                    assert_eq!(inpainted[y][x], original[y][x]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn inpaint_overlapping_blends_in() {
+        // every row alternates, so the masked region can only be filled one way
+        let input = vec![vec!['a', 'b'], vec!['a', 'b']];
+        let tiles = patterns::extract(&input, 2, Symmetry::NONE, Periodicity::Both);
+        let model = Arc::new(Model::new(tiles, Compatibility::Overlapping).unwrap());
+
+        let image: Vec<Vec<_>> = (0..5).map(|_| "ababab".chars().collect()).collect();
+        let mask = mask(6, 5, (1, 1), (4, 4));
+
+        let mut wave = model.wave(5, 4).with_periodicity(Periodicity::Neither);
+        assert_eq!(wave.grid_size(), (6, 5));
+        wave.inpaint(&image, &mask).unwrap();
+        wave.run().unwrap();
+
+        assert_eq!(wave.to_grid().unwrap(), image);
+    }
+
+    #[test]
+    fn inpaint_reports_unknown_patterns() {
+        let input = vec![vec!['a', 'b'], vec!['a', 'b']];
+        let tiles = patterns::extract(&input, 2, Symmetry::NONE, Periodicity::Both);
+        let model = Arc::new(Model::new(tiles, Compatibility::Overlapping).unwrap());
+
+        let image: Vec<Vec<_>> = (0..4).map(|_| "aabb".chars().collect()).collect();
+        let mask = mask(4, 4, (0, 0), (0, 0));
+
+        let mut wave = model.wave(4, 4);
+        assert!(wave.inpaint(&image, &mask).is_err());
+    }
+}
@@ -1,104 +1,173 @@
 use std::collections::VecDeque;
 use std::error::Error;
+use std::sync::Arc;
 
-use crate::cells::{Cell, WaysToBecomeTile};
+use crate::backtracking::{Backtracking, Change, Decision};
+use crate::cells::Cell;
+use crate::events::{Event, Events, Step, Steps};
+use crate::model::Model;
+use crate::patterns::Compatibility;
+use crate::renderers::CellRenderer;
+use crate::retry::RetryReport;
+use crate::selectors::{CellSelector, Entropy};
 use crate::slots::Location;
-use crate::tiles::{Tile, TileTable};
+use crate::tiles::{Tile, TileId};
 
 use enum_map::EnumMap;
-use image::{Pixel, RgbaImage};
+use image::{Rgba, RgbaImage};
 use rand::prelude::*;
-use strum::{Display, IntoEnumIterator};
+use rand_chacha::ChaCha8Rng;
+use strum::Display;
 use tiles::RemovedTile;
 
+mod backtracking;
+pub mod batch;
+mod bitset;
 pub mod cells;
+pub mod events;
+mod inpainting;
+pub mod model;
 pub mod patterns;
-mod slots;
+pub mod recording;
+pub mod renderers;
+pub mod retry;
+pub mod rules;
+#[cfg(feature = "serde")]
+pub mod saving;
+pub mod selectors;
+pub mod slots;
+#[cfg(test)]
+mod test_util;
+pub mod tiled;
 pub mod tiles;
 
+/// The random number generator used by a wave unless another is given with
+/// [`Wave::with_rng`], its output is the same on every platform.
+pub type DefaultRng = ChaCha8Rng;
+
+/// Which edges of a grid wrap around to the opposite edge.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Periodicity {
+    /// The grid is a torus, every cell has four neighbors.
+    #[default]
+    Both,
+    /// The left and right edges wrap around.
+    Horizontal,
+    /// The top and bottom edges wrap around.
+    Vertical,
+    /// The grid has real edges on all sides.
+    Neither,
+}
+
+impl Periodicity {
+    pub fn wraps_horizontally(&self) -> bool {
+        matches!(self, Periodicity::Both | Periodicity::Horizontal)
+    }
+
+    pub fn wraps_vertically(&self) -> bool {
+        matches!(self, Periodicity::Both | Periodicity::Vertical)
+    }
+}
+
 #[derive(Debug)]
-pub struct Wave<'a, Data> {
-    cells: Box<[Cell<'a, Data>]>,
+pub struct Wave<Data, R = DefaultRng> {
+    model: Arc<Model<Data>>,
+    cells: Box<[Cell]>,
+    backtracking: Option<Backtracking>,
+    /// removed tiles which have not been propagated yet
+    pending: VecDeque<RemovedTile>,
+    events: Events,
+    selector: Box<dyn CellSelector>,
     x_cells: usize,
     y_cells: usize,
-    size: usize,
+    periodicity: Periodicity,
+    /// whether the tiles no neighbor can support were removed yet
+    unsupported_removed: bool,
+    /// the error of a contradiction which was not undone, returned again by
+    /// every later change
+    failed: Option<WaveCollapseError>,
     num_collapsed: usize,
+    rng: R,
 }
 
-impl<'a, Data: PartialEq> Wave<'a, Data> {
-    pub fn new(tiles: &'a Box<[Tile<Data>]>, x_cells: usize, y_cells: usize, size: usize) -> Self {
-        let mut ways_to_become_tile: TileTable<WaysToBecomeTile> =
-            TileTable(vec![WaysToBecomeTile::default(); tiles.len()].into_boxed_slice());
-
-        for tile in tiles.iter() {
-            for neighbor in tiles.iter() {
-                for location in Location::iter() {
-                    if tile.is_compatible(neighbor, location) {
-                        ways_to_become_tile[tile][location] += 1;
-                    }
-                }
-            }
-        }
-
-        let tile_table = TileTable(tiles.iter().map(|t| Some(t)).collect());
+impl<Data> Wave<Data> {
+    /// Creates a wave where any tile of the model may still be choosen for
+    /// every cell.
+    ///
+    /// The cells are stored row by row, cell `index` is in column
+    /// `index % x_cells` and row `index / x_cells`.
+    pub fn new(model: Arc<Model<Data>>, x_cells: usize, y_cells: usize) -> Self {
+        assert!(
+            x_cells > 0 && y_cells > 0,
+            "a wave needs at least one cell in each direction"
+        );
 
         let cells: Box<_> = (0..(x_cells * y_cells))
-            .map(|_i| Cell::new(ways_to_become_tile.clone(), tile_table.clone()))
+            .map(|_i| Cell::new(&model))
             .collect();
+        // with a single tile every cell starts out collapsed
+        let num_collapsed = cells.iter().filter(|c| c.collapsed()).count();
 
         Self {
+            model,
             cells,
+            backtracking: None,
+            pending: VecDeque::new(),
+            events: Events::default(),
+            selector: Box::new(Entropy::default()),
             x_cells,
             y_cells,
-            size,
-            num_collapsed: 0,
+            periodicity: Periodicity::default(),
+            unsupported_removed: false,
+            failed: None,
+            num_collapsed,
+            rng: DefaultRng::from_entropy(),
         }
     }
+}
 
-    fn get_lowest_entropy_cells(&self) -> Box<[usize]> {
-        let mut uncolapsed_cells: Box<_> = self
-            .cells
-            .iter()
-            .enumerate()
-            .filter_map(|(i, c)| {
-                if c.uncollapsed() {
-                    Some((i, c.entropy()))
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        uncolapsed_cells.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
-
-        assert!(uncolapsed_cells.len() > 0);
-        let min = uncolapsed_cells[0].1;
-
-        uncolapsed_cells
-            .into_iter()
-            .filter_map(|(i, c)| if *c == min { Some(*i) } else { None })
-            .collect()
+impl<Data, R: Rng + SeedableRng> Wave<Data, R> {
+    /// Seeds the random number generator of the wave, the same seed, tiles
+    /// and dimensions always collapse to the same result.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = R::seed_from_u64(seed);
+        self
     }
+}
 
-    fn get_neighbors(&self, index: usize) -> EnumMap<Location, usize> {
+impl<Data, R> Wave<Data, R> {
+    /// The index of the neighboring cell in each direction, edge cells have
+    /// no neighbor past the edges which do not wrap around.
+    fn get_neighbors(&self, index: usize) -> EnumMap<Location, Option<usize>> {
         let row = index / self.x_cells;
         let col = index % self.x_cells;
+        let wrap_x = self.periodicity.wraps_horizontally();
+        let wrap_y = self.periodicity.wraps_vertically();
 
-        let north = {
+        let north = if row > 0 || wrap_y {
             let new_row = (row + self.y_cells - 1) % self.y_cells;
-            new_row * self.x_cells + col
+            Some(new_row * self.x_cells + col)
+        } else {
+            None
         };
-        let east = {
+        let east = if col + 1 < self.x_cells || wrap_x {
             let new_col = (col + 1) % self.x_cells;
-            row * self.x_cells + new_col
+            Some(row * self.x_cells + new_col)
+        } else {
+            None
         };
-        let south = {
+        let south = if row + 1 < self.y_cells || wrap_y {
             let new_row = (row + 1) % self.y_cells;
-            new_row * self.x_cells + col
+            Some(new_row * self.x_cells + col)
+        } else {
+            None
         };
-        let west = {
+        let west = if col > 0 || wrap_x {
             let new_col = (col + self.x_cells - 1) % self.x_cells;
-            (row * self.x_cells) + new_col
+            Some((row * self.x_cells) + new_col)
+        } else {
+            None
         };
 
         let mut out = EnumMap::default();
@@ -110,57 +179,501 @@ impl<'a, Data: PartialEq> Wave<'a, Data> {
         out
     }
 
-    pub fn collapse(&mut self) -> Result<bool, WaveCollapseError> {
+    /// The neighboring cells which exist, see [`Wave::get_neighbors`].
+    fn neighbors(&self, index: usize) -> impl Iterator<Item = (Location, usize)> {
+        self.get_neighbors(index)
+            .into_iter()
+            .filter_map(|(location, neighbor)| Some((location, neighbor?)))
+    }
+}
+
+impl<Data, R: Rng> Wave<Data, R> {
+    /// Replaces the random number generator which drives every choice the
+    /// wave makes.
+    pub fn with_rng<Rng2: Rng>(self, rng: Rng2) -> Wave<Data, Rng2> {
+        Wave {
+            model: self.model,
+            cells: self.cells,
+            backtracking: self.backtracking,
+            pending: self.pending,
+            events: self.events,
+            selector: self.selector,
+            x_cells: self.x_cells,
+            y_cells: self.y_cells,
+            periodicity: self.periodicity,
+            unsupported_removed: self.unsupported_removed,
+            failed: self.failed,
+            num_collapsed: self.num_collapsed,
+            rng,
+        }
+    }
+
+    /// Sets which edges of the wave wrap around to the opposite edge, by
+    /// default both do so the output tiles seamlessly.
+    pub fn with_periodicity(mut self, periodicity: Periodicity) -> Self {
+        self.periodicity = periodicity;
+        self
+    }
+
+    /// Enables backtracking, when a cell runs out of tiles the most recent
+    /// choices are undone and the tiles choosen are banned instead of failing
+    /// the collapse.
+    ///
+    /// At most `budget` choices are undone over the life of the wave before
+    /// [`WaveCollapseError::OutOfBacktracks`] is returned.
+    pub fn with_backtracking(mut self, budget: usize) -> Self {
+        self.backtracking = Some(Backtracking::new(budget));
+        self
+    }
+
+    /// Replaces how the next cell to observe is picked, by default the cell
+    /// with the lowest [`Entropy`].
+    pub fn with_selector<S: CellSelector + 'static>(mut self, selector: S) -> Self {
+        self.selector = Box::new(selector);
+        self
+    }
+
+    /// Calls `listener` with every [`Event`] of the wave from now on, such as
+    /// to follow the collapse live.
+    pub fn with_listener<F: FnMut(&Event) + Send + 'static>(mut self, listener: F) -> Self {
+        let bans = self.events.bans;
+        self.events = Events::new(Box::new(listener));
+        self.events.bans = bans;
+        self
+    }
+
+    /// The number of choices undone so far.
+    pub fn backtracks(&self) -> usize {
+        self.backtracking.as_ref().map_or(0, |b| b.backtracks)
+    }
+
+    /// Observes a single cell and propagates the choice, backtracking out of
+    /// contradictions if enabled.
+    ///
+    /// Returns `None` if no cell was left to observe after removing the tiles
+    /// no neighbor can support, which is done before the first observation.
+    /// Once a contradiction was not undone every later call returns its error.
+    pub fn collapse(&mut self) -> Result<Option<Step>, WaveCollapseError> {
+        if let Some(error) = self.failed {
+            return Err(error);
+        }
         if self.collapsed() {
             return Err(WaveCollapseError::AlreadyCollapsed);
         }
 
-        let mut rng = rand::thread_rng();
+        self.remove_unsupported()?;
 
-        let lowest_entropy = self.get_lowest_entropy_cells();
-        let entropy_index = rng.gen_range(0..lowest_entropy.len());
+        if self.collapsed() {
+            return Ok(None);
+        }
+
+        let (bans, num_collapsed, backtracks) =
+            (self.events.bans, self.num_collapsed, self.backtracks());
 
-        let index = lowest_entropy[entropy_index];
+        let index = self
+            .selector
+            .select(&self.cells, self.x_cells, &mut self.rng);
         let cell = &mut self.cells[index];
+        let choosen = cell.choose_tile(&self.model, &mut self.rng);
 
-        let mut removed_tiles: VecDeque<_> = cell
-            .collapse(&mut rng)
-            .into_iter()
-            .map(|tile| RemovedTile {
+        if let Some(backtracking) = &mut self.backtracking {
+            backtracking.decisions.push(Decision {
                 cell_index: index,
-                tile,
-            })
-            .collect();
+                tile: choosen,
+                trail_len: backtracking.trail.len(),
+                num_collapsed: self.num_collapsed,
+            });
+        }
+
+        self.collapse_cell(index, choosen);
+
+        if let Err(invalid) = self.propagate() {
+            self.backtrack(invalid).map_err(|error| self.fail(error))?;
+        }
+
+        Ok(Some(Step {
+            cell_index: index,
+            tile: choosen,
+            bans: self.events.bans - bans,
+            newly_collapsed: self.num_collapsed.saturating_sub(num_collapsed),
+            backtracks: self.backtracks() - backtracks,
+        }))
+    }
+
+    /// Iterates over the steps of collapsing the wave, see
+    /// [`Wave::collapse`], ending once every cell is collapsed or after the
+    /// first error.
+    pub fn steps(&mut self) -> Steps<'_, Data, R> {
+        Steps::new(self)
+    }
+
+    /// Collapses the cell at `index` to the tile `tile` and propagates it,
+    /// such as to place an entrance or a known border before the wave is
+    /// collapsed.
+    ///
+    /// Returns [`WaveCollapseError::InvalidCell`] without changing the wave if
+    /// the tile is no longer possible in the cell. The error is also returned
+    /// if any cell is left without tiles, when backtracking the change is
+    /// then undone again, otherwise the wave can not be collapsed any
+    /// further. [`WaveCollapseError::Unsatisfiable`] is returned before any
+    /// change if the model can not fill the wave at all.
+    pub fn observe(&mut self, index: usize, tile: TileId) -> Result<(), WaveCollapseError> {
+        self.prepare_change()?;
+
+        if !self.cells[index].is_remaining(tile) {
+            return Err(WaveCollapseError::InvalidCell(index));
+        }
+
+        self.constrain(|wave| {
+            wave.collapse_cell(index, tile);
+            wave.propagate()
+        })
+    }
+
+    /// Removes the tile `tile` from the cell at `index` and propagates it,
+    /// contradictions are reported like [`Wave::observe`].
+    pub fn ban(&mut self, index: usize, tile: TileId) -> Result<(), WaveCollapseError> {
+        self.constrain(|wave| wave.ban_choice(index, tile))
+    }
+
+    /// Applies a change which is not choosen by the wave itself, undoing it if
+    /// it leads to a contradiction and backtracking is enabled.
+    ///
+    /// Backtracking past a choice of the wave also undoes the changes made
+    /// after it, so they should be made before the wave is collapsed.
+    fn constrain<F>(&mut self, change: F) -> Result<(), WaveCollapseError>
+    where
+        F: FnOnce(&mut Self) -> Result<(), usize>,
+    {
+        self.prepare_change()?;
+
+        let trail_len = self.backtracking.as_ref().map(|b| b.trail.len());
+        let num_collapsed = self.num_collapsed;
+
+        match change(self) {
+            Ok(()) => Ok(()),
+            Err(invalid) => match trail_len {
+                Some(trail_len) => {
+                    self.undo(trail_len);
+                    self.num_collapsed = num_collapsed;
+
+                    Err(WaveCollapseError::InvalidCell(invalid))
+                }
+                None => Err(self.fail(WaveCollapseError::InvalidCell(invalid))),
+            },
+        }
+    }
+
+    /// Returns the error of a failed wave, and otherwise removes the tiles no
+    /// neighbor can support before the first change.
+    fn prepare_change(&mut self) -> Result<(), WaveCollapseError> {
+        if let Some(error) = self.failed {
+            return Err(error);
+        }
+
+        self.remove_unsupported()
+    }
+
+    /// Remembers a contradiction which can not be undone, so that the wave is
+    /// not collapsed any further.
+    fn fail(&mut self, error: WaveCollapseError) -> WaveCollapseError {
+        self.failed = Some(error);
+        error
+    }
+
+    /// Removes every tile other than `choosen` from the cell, leaving the
+    /// removals to be propagated.
+    fn collapse_cell(&mut self, index: usize, choosen: TileId) {
+        let cell = &mut self.cells[index];
+        let already_collapsed = cell.collapsed();
+
+        let (pending, backtracking, events) =
+            (&mut self.pending, &mut self.backtracking, &mut self.events);
+        cell.collapse(self.model.tile(choosen), |tile| {
+            Self::queue_removed(
+                pending,
+                backtracking,
+                events,
+                RemovedTile {
+                    cell_index: index,
+                    tile,
+                },
+            )
+        });
+
+        self.selector.cell_changed(index, cell);
+        self.events.emit(Event::Observed {
+            cell_index: index,
+            tile: choosen,
+        });
+
+        if !already_collapsed {
+            self.num_collapsed += 1;
+        }
+    }
+
+    /// Queues the removal of a tile to be propagated, recording it when
+    /// backtracking.
+    fn queue_removed(
+        pending: &mut VecDeque<RemovedTile>,
+        backtracking: &mut Option<Backtracking>,
+        events: &mut Events,
+        removed: RemovedTile,
+    ) {
+        events.emit(Event::Banned {
+            cell_index: removed.cell_index,
+            tile: removed.tile,
+        });
+
+        if let Some(backtracking) = backtracking {
+            backtracking.trail.push(Change::Removed(removed));
+        }
 
-        self.num_collapsed += 1;
+        pending.push_back(removed);
+    }
 
-        while !self.collapsed() && removed_tiles.len() > 0 {
-            let removed = removed_tiles.pop_front().unwrap();
+    /// Propagates the pending removal of tiles to the neighbors of their cells
+    /// until no more tiles are removed, returning the index of the first cell
+    /// left without any tiles.
+    fn propagate(&mut self) -> Result<(), usize> {
+        while let Some(removed) = self.pending.pop_front() {
+            let mut invalid = None;
 
-            for (focus_location, focus_index) in self.get_neighbors(removed.cell_index) {
+            for (focus_location, focus_index) in self.neighbors(removed.cell_index) {
                 let focus = &mut self.cells[focus_index];
                 let focus_already_collapsed = focus.collapsed();
+                let focus_remaining_tiles = focus.num_remaining_tiles();
 
-                if let Some(no_longer_valid) =
-                    focus.removed_neighbor_tile(&removed.tile, focus_location.opposite())
-                {
-                    removed_tiles.push_back(RemovedTile {
-                        cell_index: focus_index,
-                        tile: no_longer_valid,
-                    });
+                // every tile that the removed tile allowed in the focus cell
+                // has lost one way to be supported from the removed tile's
+                // direction
+                for tile in self.model.allowed(removed.tile, focus_location) {
+                    let tile = self.model.tile(*tile);
+                    if let Some(no_longer_valid) =
+                        focus.removed_neighbor_tile(&self.model, tile, focus_location.opposite())
+                    {
+                        Self::queue_removed(
+                            &mut self.pending,
+                            &mut self.backtracking,
+                            &mut self.events,
+                            RemovedTile {
+                                cell_index: focus_index,
+                                tile: no_longer_valid,
+                            },
+                        );
+                    }
+                }
+
+                if focus.num_remaining_tiles() != focus_remaining_tiles {
+                    self.selector.cell_changed(focus_index, focus);
                 }
 
                 if focus.invalid() {
-                    return Err(WaveCollapseError::InvalidCell(focus_index));
+                    invalid.get_or_insert(focus_index);
                 } else if !focus_already_collapsed && focus.collapsed() {
                     self.num_collapsed += 1;
                 }
             }
+
+            // the removal is only recorded once it reached every neighbor so
+            // that undoing it restores exactly what was decremented
+            if let Some(backtracking) = &mut self.backtracking {
+                backtracking.trail.push(Change::Propagated(removed));
+            }
+
+            if let Some(invalid) = invalid {
+                self.pending.clear();
+                self.events.emit(Event::Contradiction {
+                    cell_index: invalid,
+                });
+                return Err(invalid);
+            }
         }
 
-        Ok(false)
+        Ok(())
     }
 
+    /// Undoes the most recent choices until banning the choosen tile no longer
+    /// leaves a cell without tiles.
+    fn backtrack(&mut self, mut invalid: usize) -> Result<(), WaveCollapseError> {
+        loop {
+            let backtracking = match &mut self.backtracking {
+                Some(backtracking) => backtracking,
+                None => return Err(WaveCollapseError::InvalidCell(invalid)),
+            };
+
+            if backtracking.backtracks >= backtracking.budget {
+                return Err(WaveCollapseError::OutOfBacktracks(invalid));
+            }
+
+            let decision = match backtracking.decisions.pop() {
+                Some(decision) => decision,
+                None => return Err(WaveCollapseError::InvalidCell(invalid)),
+            };
+
+            backtracking.backtracks += 1;
+
+            self.undo(decision.trail_len);
+            self.num_collapsed = decision.num_collapsed;
+
+            match self.ban_choice(decision.cell_index, decision.tile) {
+                Ok(()) => return Ok(()),
+                Err(next_invalid) => invalid = next_invalid,
+            }
+        }
+    }
+
+    /// Removes a tile which lead to a contradiction from an uncollapsed cell.
+    fn ban_choice(&mut self, index: usize, tile: TileId) -> Result<(), usize> {
+        self.remove_tile(index, tile)?;
+        self.propagate()
+    }
+
+    /// Removes the tiles which no tile of one of the neighbors of their cell
+    /// allows, their support is never decremented so propagation alone does
+    /// not remove them.
+    ///
+    /// Only the first successful call changes the wave, a contradiction can
+    /// not be undone as no choice lead to it.
+    fn remove_unsupported(&mut self) -> Result<(), WaveCollapseError> {
+        if self.unsupported_removed {
+            return Ok(());
+        }
+
+        match self.remove_unsupported_tiles() {
+            Ok(()) => {
+                self.unsupported_removed = true;
+                Ok(())
+            }
+            Err(invalid) => Err(self.fail(WaveCollapseError::Unsatisfiable(invalid))),
+        }
+    }
+
+    fn remove_unsupported_tiles(&mut self) -> Result<(), usize> {
+        let model = Arc::clone(&self.model);
+
+        for index in 0..self.cells.len() {
+            for (location, _) in self.neighbors(index) {
+                for tile in model.unsupported(location) {
+                    self.remove_tile(index, *tile)?;
+                }
+            }
+        }
+
+        self.propagate()
+    }
+
+    /// Removes a tile from a cell and queues it to be propagated, returning
+    /// the index of the cell if it is left without tiles.
+    fn remove_tile(&mut self, index: usize, tile: TileId) -> Result<(), usize> {
+        let cell = &mut self.cells[index];
+
+        if cell.remove_tile(self.model.tile(tile)).is_none() {
+            return Ok(());
+        }
+
+        self.selector.cell_changed(index, cell);
+
+        if cell.collapsed() {
+            self.num_collapsed += 1;
+        }
+
+        Self::queue_removed(
+            &mut self.pending,
+            &mut self.backtracking,
+            &mut self.events,
+            RemovedTile {
+                cell_index: index,
+                tile,
+            },
+        );
+
+        if cell.invalid() {
+            self.pending.clear();
+            self.events.emit(Event::Contradiction { cell_index: index });
+            return Err(index);
+        }
+
+        Ok(())
+    }
+
+    /// Reverts every change recorded after the first `trail_len` changes.
+    fn undo(&mut self, trail_len: usize) {
+        let mut trail = match &mut self.backtracking {
+            Some(backtracking) => std::mem::take(&mut backtracking.trail),
+            None => return,
+        };
+
+        for change in trail.drain(trail_len..).rev() {
+            match change {
+                Change::Removed(removed) => {
+                    let cell = &mut self.cells[removed.cell_index];
+                    cell.restore_tile(self.model.tile(removed.tile));
+                    self.selector.cell_changed(removed.cell_index, cell);
+                    self.events.emit(Event::Restored {
+                        cell_index: removed.cell_index,
+                        tile: removed.tile,
+                    });
+                }
+                Change::Propagated(removed) => {
+                    for (focus_location, focus_index) in self.neighbors(removed.cell_index) {
+                        let focus = &mut self.cells[focus_index];
+
+                        for tile in self.model.allowed(removed.tile, focus_location) {
+                            let tile = self.model.tile(*tile);
+                            focus.restored_neighbor_tile(
+                                &self.model,
+                                tile,
+                                focus_location.opposite(),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(backtracking) = &mut self.backtracking {
+            backtracking.trail = trail;
+        }
+    }
+
+    /// Collapses every cell of the wave.
+    pub fn run(&mut self) -> Result<(), WaveCollapseError> {
+        while !self.collapsed() {
+            self.collapse()?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs waves created by `build` until one collapses without a
+    /// contradiction, giving up after `max_attempts` waves.
+    ///
+    /// `build` is passed the number of the attempt starting from zero.
+    pub fn run_with_retries<F>(
+        max_attempts: usize,
+        mut build: F,
+    ) -> Result<(Self, RetryReport), RetryReport>
+    where
+        F: FnMut(usize) -> Self,
+    {
+        let mut report = RetryReport::default();
+
+        for attempt in 0..max_attempts {
+            let mut wave = build(attempt);
+            report.attempts += 1;
+
+            match wave.run() {
+                Ok(()) => return Ok((wave, report)),
+                Err(error) => report.failed(error),
+            }
+        }
+
+        Err(report)
+    }
+
+    /// Whether every cell is collapsed to a tile its neighbors allow, which is
+    /// not known before the wave is first changed.
     pub fn collapsed(&self) -> bool {
         // uncomment to verify the underlying contract is upheld where cells are collapsed
         // before the num collapsed is incremented
@@ -169,36 +682,117 @@ impl<'a, Data: PartialEq> Wave<'a, Data> {
         //     self.cells.iter().all(|c| c.collapsed()),
         //     self.num_collapsed == self.cells.len()
         // );
-        self.num_collapsed == self.cells.len()
+        self.unsupported_removed && self.failed.is_none() && self.num_collapsed == self.cells.len()
+    }
+
+    pub fn choosen_tile(&self, index: usize) -> Option<&Tile<Data>> {
+        self.cells[index]
+            .choosen_tile()
+            .map(|tile| self.model.tile(tile))
+    }
+
+    /// The model the tiles of the wave are choosen from.
+    pub fn model(&self) -> &Arc<Model<Data>> {
+        &self.model
+    }
+
+    /// Renders the wave by drawing the remaining tiles of each cell with
+    /// `renderer`, such as one of the [`renderers`] or a closure.
+    ///
+    /// With [`Compatibility::Overlapping`] neighboring cells share all but
+    /// one row or column of their patterns, so each cell only contributes the
+    /// top left element of its blended pattern, one pixel per cell, other than
+    /// the last cells before an edge which does not wrap around. Otherwise
+    /// every cell is drawn as its whole pattern with a one pixel border.
+    ///
+    /// Cells left without any tiles are drawn in [`Wave::CONTRADICTION`].
+    pub fn to_image<C: CellRenderer<Data> + ?Sized>(&self, renderer: &C) -> RgbaImage {
+        match self.model.compatibility() {
+            Compatibility::Edge => self.to_tiled_image(renderer),
+            Compatibility::Overlapping => self.to_overlapping_image(renderer),
+        }
     }
 
-    pub fn to_image<T: Pixel<Subpixel = u8>>(
+    /// The color of cells without any tiles.
+    pub const CONTRADICTION: Rgba<u8> = Rgba([255, 0, 255, 255]);
+
+    fn cell_pixels<C: CellRenderer<Data> + ?Sized>(
         &self,
-        blend_cell: fn(data: &Box<[&Box<[Data]>]>) -> Box<[T]>,
-    ) -> RgbaImage {
-        let size_padding = self.size + 2;
+        index: usize,
+        renderer: &C,
+    ) -> Box<[Rgba<u8>]> {
+        let cell = &self.cells[index];
+        let size = self.model.size();
+
+        if cell.invalid() {
+            return vec![Self::CONTRADICTION; size * size].into_boxed_slice();
+        }
+
+        let remaining_tiles: Box<_> = cell
+            .remaining_tiles()
+            .map(|tile| self.model.tile(tile))
+            .collect();
+        let pixels = renderer.render(index, &remaining_tiles);
+        assert_eq!(pixels.len(), size * size);
+
+        pixels
+    }
+
+    fn to_overlapping_image<C: CellRenderer<Data> + ?Sized>(&self, renderer: &C) -> RgbaImage {
+        let size = self.model.size();
+        let (width, height) = self.grid_size();
+        // along edges which do not wrap around the patterns of the last
+        // cells are drawn whole, as nothing overlaps the rest of them
+        let x_border = width - self.x_cells;
+        let y_border = height - self.y_cells;
+
+        let mut image = image::RgbaImage::new(width as u32, height as u32);
+
+        for i in 0..self.cells.len() {
+            let pixels = self.cell_pixels(i, renderer);
+
+            let col = i % self.x_cells;
+            let row = i / self.x_cells;
+
+            let x_extent = if col + 1 == self.x_cells { x_border } else { 0 };
+            let y_extent = if row + 1 == self.y_cells { y_border } else { 0 };
+
+            for local_y in 0..=y_extent {
+                for local_x in 0..=x_extent {
+                    let pixel = pixels[local_y * size + local_x];
+                    image.put_pixel((col + local_x) as u32, (row + local_y) as u32, pixel);
+                }
+            }
+        }
+
+        image
+    }
+
+    fn to_tiled_image<C: CellRenderer<Data> + ?Sized>(&self, renderer: &C) -> RgbaImage {
+        let size = self.model.size();
+        let size_padding = size + 2;
         let mut image = image::RgbaImage::new(
             (size_padding * self.x_cells) as u32,
             (size_padding * self.y_cells) as u32,
         );
 
-        for (i, cell) in self.cells.iter().enumerate() {
-            let pixels = cell.to_image(blend_cell);
+        for i in 0..self.cells.len() {
+            let pixels = self.cell_pixels(i, renderer);
 
-            let row = i / self.y_cells;
             let col = i % self.x_cells;
+            let row = i / self.x_cells;
 
             let cell_x = col * size_padding + 1;
             let cell_y = row * size_padding + 1;
 
-            for (j, pixel) in pixels.into_iter().enumerate() {
-                let local_x = j % self.size;
-                let local_y = j / self.size;
+            for (j, pixel) in pixels.iter().enumerate() {
+                let local_x = j % size;
+                let local_y = j / size;
 
                 let x = (local_x + cell_x) as u32 % image.width();
                 let y = (local_y + cell_y) as u32 % image.height();
 
-                image.put_pixel(x, y, pixel.to_rgba());
+                image.put_pixel(x, y, *pixel);
             }
         }
 
@@ -206,10 +800,338 @@ impl<'a, Data: PartialEq> Wave<'a, Data> {
     }
 }
 
-#[derive(Debug, Display)]
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum WaveCollapseError {
     InvalidCell(usize),
     AlreadyCollapsed,
+    OutOfBacktracks(usize),
+    /// Removing the tiles no neighbor can support left the cell without
+    /// tiles, so no choice can collapse a wave of this size and periodicity.
+    Unsatisfiable(usize),
 }
 
 impl Error for WaveCollapseError {}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use image::Rgba;
+    use proptest::prelude::*;
+    use strum::IntoEnumIterator;
+
+    use crate::model::Model;
+    use crate::patterns::{self, Compatibility, Pattern, Symmetry};
+    use crate::retry::derive_seed;
+    use crate::slots::Location;
+    use crate::test_util::assert_adjacencies;
+    use crate::tiled::TiledModel;
+    use crate::tiles::{Tile, TileId};
+    use crate::{Periodicity, WaveCollapseError};
+
+    fn model() -> Arc<Model<char>> {
+        let mut model = TiledModel::new();
+        let tiles: Box<_> = ['a', 'b', 'c', 'd']
+            .into_iter()
+            .zip([1.0, 2.0, 0.5, 1.5])
+            .map(|(data, weight)| {
+                model.add_tile(Pattern::new(vec![data].into_boxed_slice()), weight)
+            })
+            .collect();
+
+        // anything may be adjacent other than 'a' and 'd'
+        for a in tiles.iter() {
+            let forbidden = [[tiles[0], tiles[3]], [tiles[3], tiles[0]]];
+            for b in tiles.iter().filter(|b| !forbidden.contains(&[*a, **b])) {
+                for location in Location::iter() {
+                    model.allow(*a, location, *b);
+                }
+            }
+        }
+
+        Arc::new(model.build().unwrap())
+    }
+
+    fn choosen_tiles(model: &Arc<Model<char>>, seed: u64) -> Vec<char> {
+        let mut wave = model.wave(12, 9).with_seed(seed);
+        wave.run().unwrap();
+
+        (0..12 * 9)
+            .map(|i| wave.choosen_tile(i).unwrap().data()[0])
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_output() {
+        let model = model();
+
+        for seed in 0..5 {
+            assert_eq!(choosen_tiles(&model, seed), choosen_tiles(&model, seed));
+        }
+    }
+
+    #[test]
+    fn different_seeds_differ() {
+        let model = model();
+        let outputs: Vec<_> = (0..5)
+            .map(|seed| choosen_tiles(&model, derive_seed(7, seed)))
+            .collect();
+
+        assert!(outputs.iter().skip(1).any(|o| *o != outputs[0]));
+    }
+
+    #[test]
+    fn edges_without_wrapping_have_no_neighbors() {
+        let model = model();
+        let wave = model.wave(3, 2).with_periodicity(Periodicity::Horizontal);
+
+        let corner = wave.get_neighbors(0);
+        assert_eq!(corner[Location::North], None);
+        assert_eq!(corner[Location::East], Some(1));
+        assert_eq!(corner[Location::South], Some(3));
+        assert_eq!(corner[Location::West], Some(2));
+
+        let corner = wave.get_neighbors(5);
+        assert_eq!(corner[Location::South], None);
+        assert_eq!(corner[Location::East], Some(3));
+    }
+
+    #[test]
+    fn bounded_rows_need_not_tile() {
+        // a row must read 'a', 'b', 'c' from west to east, which can not wrap
+        // around
+        let mut model = TiledModel::new();
+        let tiles: Box<_> = ['a', 'b', 'c']
+            .into_iter()
+            .map(|data| model.add_tile(Pattern::new(vec![data].into_boxed_slice()), 1.0))
+            .collect();
+        model.allow(tiles[0], Location::East, tiles[1]);
+        model.allow(tiles[1], Location::East, tiles[2]);
+        for tile in tiles.iter() {
+            model.allow(*tile, Location::South, *tile);
+        }
+        let model = Arc::new(model.build().unwrap());
+
+        let mut wave = model
+            .wave(3, 4)
+            .with_periodicity(Periodicity::Vertical)
+            .with_seed(1);
+        wave.run().unwrap();
+        for index in 0..12 {
+            assert_eq!(wave.choosen_tile(index).unwrap().id(), tiles[index % 3]);
+        }
+
+        let mut wave = model.wave(3, 4).with_seed(1);
+        let error = wave.run().unwrap_err();
+        assert!(matches!(error, WaveCollapseError::Unsatisfiable(_)));
+        assert_eq!(wave.run().unwrap_err(), error);
+        assert!(!wave.collapsed());
+
+        // backtracking can not undo it either
+        let mut wave = model.wave(3, 4).with_backtracking(10);
+        let error = wave.ban(0, tiles[1]).unwrap_err();
+        assert!(matches!(error, WaveCollapseError::Unsatisfiable(_)));
+        assert_eq!(wave.run().unwrap_err(), error);
+        assert!(!wave.collapsed());
+    }
+
+    #[test]
+    fn single_tiles_must_allow_themselves() {
+        let mut model = TiledModel::new();
+        model.add_tile(Pattern::new(vec!['a'].into_boxed_slice()), 1.0);
+        let model = Arc::new(model.build().unwrap());
+
+        let mut wave = model.wave(2, 2);
+        assert!(!wave.collapsed());
+        let error = wave.run().unwrap_err();
+        assert!(matches!(error, WaveCollapseError::Unsatisfiable(_)));
+        assert_eq!(wave.collapse().unwrap_err(), error);
+        assert!(!wave.collapsed());
+    }
+
+    #[test]
+    fn observe_and_ban_propagate() {
+        let model = model();
+        let [a, b, c, d] = [0usize, 1, 2, 3].map(TileId::from);
+        let mut wave = model.wave(4, 4).with_seed(2);
+
+        wave.observe(5, a).unwrap();
+        assert_eq!(wave.choosen_tile(5).unwrap().id(), a);
+        for (_, neighbor) in wave.neighbors(5) {
+            assert!(!wave.cells[neighbor].is_remaining(d));
+        }
+
+        wave.ban(6, b).unwrap();
+        wave.ban(6, c).unwrap();
+        assert_eq!(wave.choosen_tile(6).unwrap().id(), a);
+
+        wave.run().unwrap();
+        assert_eq!(wave.choosen_tile(5).unwrap().id(), a);
+        assert_eq!(wave.choosen_tile(6).unwrap().id(), a);
+    }
+
+    #[test]
+    fn contradictions_are_reported() {
+        let model = model();
+        let [a, b, c, d] = [0usize, 1, 2, 3].map(TileId::from);
+
+        // a tile which is no longer possible is rejected without any change
+        let mut wave = model.wave(4, 4).with_seed(1);
+        wave.observe(0, a).unwrap();
+        assert!(matches!(
+            wave.observe(1, d),
+            Err(WaveCollapseError::InvalidCell(1))
+        ));
+        wave.observe(1, b).unwrap();
+        wave.run().unwrap();
+        assert_eq!(wave.choosen_tile(1).unwrap().id(), b);
+
+        let mut wave = model.wave(4, 4);
+        wave.observe(0, a).unwrap();
+        wave.ban(1, a).unwrap();
+        wave.ban(1, b).unwrap();
+        assert!(matches!(
+            wave.ban(1, c),
+            Err(WaveCollapseError::InvalidCell(1))
+        ));
+        // without backtracking the wave can not be collapsed any further
+        assert!(matches!(wave.run(), Err(WaveCollapseError::InvalidCell(1))));
+        assert!(matches!(
+            wave.observe(2, b),
+            Err(WaveCollapseError::InvalidCell(1))
+        ));
+
+        // when backtracking the contradicting ban is undone
+        let mut wave = model.wave(4, 4).with_backtracking(0);
+        wave.observe(0, a).unwrap();
+        wave.ban(1, a).unwrap();
+        wave.ban(1, b).unwrap();
+        assert!(matches!(
+            wave.ban(1, c),
+            Err(WaveCollapseError::InvalidCell(1))
+        ));
+        assert_eq!(wave.choosen_tile(1).unwrap().id(), c);
+
+        wave.run().unwrap();
+        assert_eq!(wave.choosen_tile(1).unwrap().id(), c);
+    }
+
+    #[test]
+    fn waves_move_between_threads() {
+        let model = model();
+        let mut wave = model.wave(6, 6).with_seed(1);
+        wave.collapse().unwrap();
+
+        // the wave keeps its own handle to the model
+        drop(model);
+        let wave = std::thread::spawn(move || {
+            wave.run().unwrap();
+            wave
+        })
+        .join()
+        .unwrap();
+
+        assert!(wave.collapsed());
+    }
+
+    #[test]
+    fn thin_waves_collapse() {
+        let model = model();
+
+        for (x_cells, y_cells) in [(1, 9), (9, 1), (1, 1)] {
+            for periodicity in [Periodicity::Both, Periodicity::Neither] {
+                let mut wave = model
+                    .wave(x_cells, y_cells)
+                    .with_periodicity(periodicity)
+                    .with_seed(3);
+                wave.run().unwrap();
+
+                assert_adjacencies(&wave, &model);
+            }
+        }
+    }
+
+    fn index_color(index: usize) -> Rgba<u8> {
+        Rgba([index as u8, (index >> 8) as u8, 0, 255])
+    }
+
+    /// draws every element of a cell in the color of its index
+    fn by_index<Data>(size: usize) -> impl Fn(usize, &[&Tile<Data>]) -> Box<[Rgba<u8>]> {
+        move |index, _tiles| vec![index_color(index); size * size].into_boxed_slice()
+    }
+
+    fn periodicity() -> impl Strategy<Value = Periodicity> {
+        prop_oneof![
+            Just(Periodicity::Both),
+            Just(Periodicity::Horizontal),
+            Just(Periodicity::Vertical),
+            Just(Periodicity::Neither),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn neighbors_are_adjacent(x_cells in 1..12usize, y_cells in 1..12usize, periodicity in periodicity()) {
+            let model = model();
+            let wave = model.wave(x_cells, y_cells).with_periodicity(periodicity);
+
+            for index in 0..x_cells * y_cells {
+                let (col, row) = (index % x_cells, index / x_cells);
+                let neighbors = wave.get_neighbors(index);
+
+                for location in Location::iter() {
+                    let (dx, dy) = match location {
+                        Location::North => (0, -1),
+                        Location::East => (1, 0),
+                        Location::South => (0, 1),
+                        Location::West => (-1, 0),
+                    };
+                    let (x, y) = (col as isize + dx, row as isize + dy);
+                    let inside = (0..x_cells as isize).contains(&x) && (0..y_cells as isize).contains(&y);
+                    let wraps = match location {
+                        Location::North | Location::South => periodicity.wraps_vertically(),
+                        Location::East | Location::West => periodicity.wraps_horizontally(),
+                    };
+
+                    let expected = (inside || wraps).then(|| {
+                        y.rem_euclid(y_cells as isize) as usize * x_cells + x.rem_euclid(x_cells as isize) as usize
+                    });
+                    prop_assert_eq!(neighbors[location], expected);
+
+                    if let Some(neighbor) = expected {
+                        prop_assert_eq!(wave.get_neighbors(neighbor)[location.opposite()], Some(index));
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn tiled_cells_are_drawn_in_place(x_cells in 1..12usize, y_cells in 1..12usize) {
+            let model = model();
+            let wave = model.wave(x_cells, y_cells);
+            let image = wave.to_image(&by_index(1));
+
+            prop_assert_eq!(image.dimensions(), (3 * x_cells as u32, 3 * y_cells as u32));
+            for index in 0..x_cells * y_cells {
+                let (col, row) = ((index % x_cells) as u32, (index / x_cells) as u32);
+                prop_assert_eq!(*image.get_pixel(col * 3 + 1, row * 3 + 1), index_color(index));
+            }
+        }
+
+        #[test]
+        fn overlapping_cells_are_drawn_in_place(x_cells in 1..12usize, y_cells in 1..12usize, periodicity in periodicity()) {
+            let input = vec![vec!['a', 'b'], vec!['b', 'a']];
+            let model = Arc::new(Model::new(patterns::extract(&input, 2, Symmetry::NONE, Periodicity::Both), Compatibility::Overlapping).unwrap());
+            let wave = model.wave(x_cells, y_cells).with_periodicity(periodicity);
+            let image = wave.to_image(&by_index(2));
+
+            let (width, height) = wave.grid_size();
+            prop_assert_eq!(image.dimensions(), (width as u32, height as u32));
+            for index in 0..x_cells * y_cells {
+                let (col, row) = ((index % x_cells) as u32, (index / x_cells) as u32);
+                prop_assert_eq!(*image.get_pixel(col, row), index_color(index));
+            }
+        }
+    }
+}
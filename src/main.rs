@@ -1,31 +1,145 @@
-use hashbag::HashBag;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, ValueEnum};
 use image::io::Reader as ImageReader;
-use image::{GenericImageView, Rgba};
+use image::{ImageError, Rgba};
 use std::error::Error;
-use wfc::patterns::Pattern;
-use wfc::tiles::Tile;
-use wfc::Wave;
+use std::fs::File;
+use std::io::Read;
+#[cfg(feature = "serde")]
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+use wfc::batch::Batch;
+use wfc::model::Model;
+use wfc::patterns::{self, Compatibility, PatternSet, Symmetry};
+use wfc::recording::Recording;
+use wfc::renderers::WeightedMean;
+use wfc::retry::derive_seed;
+use wfc::{Periodicity, Wave};
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-struct Pixel(Rgba<u8>);
+/// Generates an image in the style of a sample image with the overlapping
+/// wave function collapse algorithm.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    /// The sample image to extract patterns from, or a model saved with
+    /// --save-model, in which case the pattern options are ignored
+    input: PathBuf,
 
-impl Default for Pixel {
-    fn default() -> Self {
-        Self(Rgba(Default::default()))
+    /// Where to write the generated image
+    #[arg(short, long, default_value = "output.png")]
+    output: PathBuf,
+
+    /// The width and height of the patterns in pixels
+    #[arg(short = 'n', long, default_value_t = 3)]
+    pattern_size: usize,
+
+    /// The width of the output in cells
+    #[arg(short = 'W', long, default_value_t = 20)]
+    width: usize,
+
+    /// The height of the output in cells
+    #[arg(short = 'H', long, default_value_t = 20)]
+    height: usize,
+
+    /// Seeds the generation so it can be reproduced, random otherwise
+    #[arg(short, long)]
+    seed: Option<u64>,
+
+    /// Which edges of the output wrap around
+    #[arg(short, long, value_enum, default_value_t = Edges::Both)]
+    periodicity: Edges,
+
+    /// Which edges of the input wrap around when extracting patterns
+    #[arg(long, value_enum, default_value_t = Edges::Both)]
+    input_periodicity: Edges,
+
+    /// The number of rotated and reflected variants of each pattern, 1, 2, 4 or 8
+    #[arg(long, default_value = "8", value_parser = parse_symmetry)]
+    symmetry: Symmetry,
+
+    /// How many times to try collapsing the output again after a
+    /// contradiction before giving up
+    #[arg(short, long, default_value_t = 10)]
+    retries: usize,
+
+    /// Also writes every extracted pattern to this image
+    #[arg(long)]
+    patterns: Option<PathBuf>,
+
+    /// Also writes the model, as JSON if the path ends in .json and in a
+    /// compact binary format otherwise, conventionally ending in .wfcm
+    #[cfg(feature = "serde")]
+    #[arg(long)]
+    save_model: Option<PathBuf>,
+
+    /// Generates this many outputs, numbering the output files
+    #[arg(short, long)]
+    count: Option<usize>,
+
+    /// The number of threads generating outputs, all available by default
+    #[arg(long, requires = "count")]
+    threads: Option<usize>,
+
+    /// Also records the output while it collapses, as an animated GIF if the
+    /// path ends in .gif and as numbered PNG files in this directory
+    /// otherwise
+    #[arg(long, conflicts_with = "count")]
+    record: Option<PathBuf>,
+
+    /// The number of observations between the frames of the recording
+    #[arg(long, default_value_t = 1, requires = "record")]
+    record_every: usize,
+}
+
+impl Args {
+    /// The first attempt to collapse an output and every retry.
+    fn max_attempts(&self) -> usize {
+        self.retries.saturating_add(1)
+    }
+}
+
+/// [`Periodicity`] as an argument.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Edges {
+    Both,
+    Horizontal,
+    Vertical,
+    Neither,
+}
+
+impl From<Edges> for Periodicity {
+    fn from(edges: Edges) -> Self {
+        match edges {
+            Edges::Both => Periodicity::Both,
+            Edges::Horizontal => Periodicity::Horizontal,
+            Edges::Vertical => Periodicity::Vertical,
+            Edges::Neither => Periodicity::Neither,
+        }
     }
 }
 
-fn save_patterns(
-    hashbag: &HashBag<Pattern<Pixel>>,
+fn parse_symmetry(arg: &str) -> Result<Symmetry, String> {
+    arg.parse()
+        .ok()
+        .and_then(Symmetry::from_variants)
+        .ok_or_else(|| "expected 1, 2, 4 or 8 variants".to_string())
+}
+
+fn save_patterns<P: AsRef<Path>>(
+    patterns: &PatternSet<Rgba<u8>>,
     n: u32,
     col_count: u32,
+    path: P,
 ) -> Result<(), Box<dyn Error>> {
     let n_padding = n + 2;
     let mut img = image::RgbaImage::new(
         col_count * n_padding,
-        ((hashbag.set_len() as u32 / col_count) + 1) * n_padding,
+        ((patterns.len() as u32 / col_count) + 1) * n_padding,
     );
-    for (i, (pattern, _)) in hashbag.set_iter().enumerate() {
+    for (i, (pattern, _)) in patterns.iter().enumerate() {
         let pattern_x = (i as u32 % col_count) * n_padding + 1;
         let pattern_y = (i as u32 / col_count) * n_padding + 1;
 
@@ -33,104 +147,317 @@ fn save_patterns(
             let x = pattern_x + j as u32 % n;
             let y = pattern_y + j as u32 / n;
 
-            img.put_pixel(x, y, pixel.0);
+            img.put_pixel(x, y, *pixel);
         }
     }
 
-    img.save("debug/patterns.png")?;
+    img.save(path)?;
 
     Ok(())
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let image_data = ImageReader::open("flowers.png")?.decode()?;
-    let n = 3u32;
-    let x_cells = 20;
-    let y_cells = 20;
+/// Exits with a usage error if the arguments can not produce an image.
+fn validate(args: &Args) {
+    let mut command = Args::command();
 
-    let mut patterns = HashBag::new();
+    if args.pattern_size == 0 {
+        command
+            .error(
+                ErrorKind::ValueValidation,
+                "the pattern size must be at least 1",
+            )
+            .exit();
+    }
+    if args.width == 0 || args.height == 0 {
+        command
+            .error(
+                ErrorKind::ValueValidation,
+                "the output must be at least 1 cell wide and high",
+            )
+            .exit();
+    }
+    if args.record_every == 0 {
+        command
+            .error(
+                ErrorKind::ValueValidation,
+                "at least 1 observation is needed between frames",
+            )
+            .exit();
+    }
+    if args.count == Some(0) || args.threads == Some(0) {
+        command
+            .error(
+                ErrorKind::ValueValidation,
+                "the count and threads must be at least 1",
+            )
+            .exit();
+    }
+}
 
-    for x in 0..image_data.width() {
-        for y in 0..image_data.height() {
-            let mut pixel_data = Vec::with_capacity((n * n) as usize);
-            for i in 0..n {
-                for j in 0..n {
-                    let pixel = image_data
-                        .get_pixel((x + i) % image_data.width(), (y + j) % image_data.height());
+/// `path` with the number of the output before its extension, padded to the
+/// digits of the last output.
+fn numbered(path: &Path, index: usize, count: usize) -> PathBuf {
+    let digits = (count - 1).to_string().len();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!(
+            "{}_{:0digits$}.{}",
+            stem,
+            index,
+            extension.to_string_lossy()
+        ),
+        None => format!("{}_{:0digits$}", stem, index),
+    };
 
-                    pixel_data.push(Pixel(pixel));
-                }
-            }
+    path.with_file_name(name)
+}
 
-            let pattern = Pattern::new(pixel_data.into_boxed_slice());
-            for permutation in pattern.all_permutations() {
-                patterns.insert(permutation);
-            }
-        }
+/// How a model saved with --save-model is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ModelFormat {
+    Json,
+    Binary,
+}
+
+/// The format of `path` if it is a model saved with --save-model rather than
+/// an image, recognized by its first bytes whatever its extension.
+fn saved_model_format(path: &Path) -> Option<ModelFormat> {
+    let mut start = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(64)
+        .read_to_end(&mut start)
+        .ok()?;
+
+    // the header written by `Model::save_binary`, images never start with
+    // it or with an opening brace
+    if start.starts_with(b"WFCM") {
+        Some(ModelFormat::Binary)
+    } else if start.trim_ascii_start().starts_with(b"{") {
+        Some(ModelFormat::Json)
+    } else {
+        None
     }
+}
+
+#[cfg(feature = "serde")]
+fn load_model(path: &Path, format: ModelFormat) -> Result<Model<[u8; 4]>, Box<dyn Error>> {
+    let file = BufReader::new(
+        File::open(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?,
+    );
+
+    let model = match format {
+        ModelFormat::Json => Model::load_json(file).map_err(|e| e.to_string()),
+        ModelFormat::Binary => Model::load_binary(file).map_err(|e| e.to_string()),
+    };
+
+    model.map_err(|e| format!("could not load the model {}: {}", path.display(), e).into())
+}
 
-    save_patterns(&patterns, n, 15)?;
+#[cfg(not(feature = "serde"))]
+fn load_model(path: &Path, _format: ModelFormat) -> Result<Model<[u8; 4]>, Box<dyn Error>> {
+    Err(format!(
+        "{} is a saved model, which needs the serde feature",
+        path.display()
+    )
+    .into())
+}
+
+#[cfg(feature = "serde")]
+fn save_model(model: &Model<[u8; 4]>, path: &Path) -> Result<(), Box<dyn Error>> {
+    let file = BufWriter::new(
+        File::create(path).map_err(|e| format!("could not write {}: {}", path.display(), e))?,
+    );
+
+    let saved = if path.extension().is_some_and(|e| e == "json") {
+        model.save_json(file).map_err(|e| e.to_string())
+    } else {
+        model.save_binary(file).map_err(|e| e.to_string())
+    };
+
+    saved.map_err(|e| format!("could not write {}: {}", path.display(), e).into())
+}
 
-    let pattern_count = patterns.len() as f64;
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let model = match saved_model_format(&args.input) {
+        Some(format) => load_model(&args.input, format)?,
+        None => extract_model(args)?,
+    };
 
-    let tiles: Box<_> = patterns
+    #[cfg(feature = "serde")]
+    if let Some(path) = &args.save_model {
+        save_model(&model, path)?;
+    }
+
+    let model = Arc::new(model);
+
+    match args.count {
+        Some(count) => generate_batch(args, model, count),
+        None => generate(args, &model),
+    }
+}
+
+/// Extracts the patterns of the input image, with the colors of the pixels
+/// as arrays so that the model can be saved.
+fn extract_model(args: &Args) -> Result<Model<[u8; 4]>, Box<dyn Error>> {
+    let image_data = ImageReader::open(&args.input)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| format!("could not read {}: {}", args.input.display(), e))?
+        .decode()
+        .map_err(|e| format!("could not decode {}: {}", args.input.display(), e))?
+        .to_rgba8();
+
+    let n = args.pattern_size;
+    let input_periodicity = Periodicity::from(args.input_periodicity);
+    // patterns may only leave the input across edges which wrap around
+    let (min_width, min_height) = match input_periodicity {
+        Periodicity::Both => (1, 1),
+        Periodicity::Horizontal => (1, n),
+        Periodicity::Vertical => (n, 1),
+        Periodicity::Neither => (n, n),
+    };
+    if (image_data.width() as usize) < min_width || (image_data.height() as usize) < min_height {
+        return Err(format!(
+            "{} is {}x{} pixels, too small for {}x{} patterns which do not wrap around",
+            args.input.display(),
+            image_data.width(),
+            image_data.height(),
+            n,
+            n
+        )
+        .into());
+    }
+
+    let patterns = patterns::extract_patterns(&image_data, n, args.symmetry, input_periodicity);
+
+    if let Some(path) = &args.patterns {
+        save_patterns(&patterns, n as u32, 15, path)
+            .map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+    }
+
+    let tiles = patterns
+        .into_tiles()
+        .into_vec()
         .into_iter()
-        .enumerate()
-        .map(|(id, (pattern, frequency))| {
-            Tile::new(pattern, (frequency as f64) / pattern_count, id)
-        })
+        .map(|tile| tile.map(|pixel| pixel.0))
         .collect();
 
-    let mut wave = Wave::new(&tiles, x_cells, y_cells, n as usize);
-    while !wave.collapsed() {
-        wave.collapse()?;
-    }
-
-    let image = wave.to_image(|remaining_tiles| {
-        let mut iter = remaining_tiles.into_iter().cloned();
-
-        // let mut base: Box<_> = iter
-        //     .next()
-        //     .unwrap()
-        //     .into_iter()
-        //     .map(|p| {
-        //         let raw = p.0 .0;
-        //         [
-        //             raw[0] as usize,
-        //             raw[1] as usize,
-        //             raw[2] as usize,
-        //             raw[3] as usize,
-        //         ]
-        //     })
-        //     .collect();
-
-        // iter.for_each(|tile| {
-        //     tile.into_iter().zip(base.iter_mut()).for_each(|(p, b)| {
-        //         let raw_p = p.0 .0;
-
-        //         b[0] += raw_p[0] as usize;
-        //         b[1] += raw_p[1] as usize;
-        //         b[2] += raw_p[2] as usize;
-        //         b[3] += raw_p[3] as usize;
-        //     });
-        // });
-
-        // base.into_iter()
-        //     .map(|p| {
-        //         let count = remaining_tiles.len();
-        //         Rgba([
-        //             (p[0] / count) as u8,
-        //             (p[1] / count) as u8,
-        //             (p[2] / count) as u8,
-        //             (p[3] / count) as u8,
-        //         ])
-        //     })
-        //     .collect()
-
-        iter.next().unwrap().into_iter().map(|p| p.0).collect()
+    Model::new(tiles, Compatibility::Overlapping)
+        .map_err(|e| format!("could not build a model of {}: {}", args.input.display(), e).into())
+}
+
+/// Writes the recording as an animated GIF if `path` ends in .gif, and as
+/// numbered PNG files in the directory `path` otherwise.
+fn save_recording(recording: &Recording, path: &Path) -> Result<(), Box<dyn Error>> {
+    let saved = if path.extension().is_some_and(|e| e == "gif") {
+        recording.save_gif(path, Duration::from_millis(50))
+    } else {
+        std::fs::create_dir_all(path)
+            .map_err(ImageError::IoError)
+            .and_then(|()| recording.save_png_sequence(path))
+    };
+
+    saved.map_err(|e| format!("could not write {}: {}", path.display(), e).into())
+}
+
+fn generate(args: &Args, model: &Arc<Model<[u8; 4]>>) -> Result<(), Box<dyn Error>> {
+    let mut recording = None;
+    let result = Wave::run_with_retries(args.max_attempts(), |attempt| {
+        let mut wave = model
+            .wave(args.width, args.height)
+            .with_periodicity(args.periodicity.into());
+        if let Some(seed) = args.seed {
+            wave = wave.with_seed(derive_seed(seed, attempt));
+        }
+
+        // the recorded wave is left collapsed or failed, so running it again
+        // only reports the result, and the last attempt is kept
+        if args.record.is_some() {
+            recording = Some(wave.run_recorded(args.record_every, &WeightedMean));
+        }
+
+        wave
     });
 
-    image.save("debug/debug.png")?;
+    if let (Some(path), Some(recording)) = (&args.record, &recording) {
+        save_recording(recording, path)?;
+    }
+
+    let (wave, report) =
+        result.map_err(|report| format!("could not generate an output, {}", report))?;
+
+    if !report.failures.is_empty() {
+        eprintln!("{}", report);
+    }
+
+    wave.to_image(&WeightedMean)
+        .save(&args.output)
+        .map_err(|e| format!("could not write {}: {}", args.output.display(), e))?;
 
     Ok(())
 }
+
+fn generate_batch(
+    args: &Args,
+    model: Arc<Model<[u8; 4]>>,
+    count: usize,
+) -> Result<(), Box<dyn Error>> {
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let mut batch = Batch::new(model, count, seed).with_max_attempts(args.max_attempts());
+    if let Some(threads) = args.threads {
+        batch = batch.with_threads(threads);
+    }
+
+    let report = batch.run(
+        |model| {
+            model
+                .wave(args.width, args.height)
+                .with_periodicity(args.periodicity.into())
+        },
+        |index, wave| {
+            let path = numbered(&args.output, index, count);
+            wave.to_image(&WeightedMean)
+                .save(&path)
+                .map_err(|e| format!("could not write {}: {}", path.display(), e))
+        },
+    );
+
+    let mut missing = 0;
+    for run in report.runs.iter() {
+        match &run.result {
+            Ok((Ok(()), _)) => {}
+            Ok((Err(error), _)) => {
+                eprintln!("error: {}", error);
+                missing += 1;
+            }
+            Err(attempts) => {
+                eprintln!(
+                    "run {} with seed {} failed, {}",
+                    run.index, run.seed, attempts
+                );
+                missing += 1;
+            }
+        }
+    }
+
+    println!("{} with seed {}", report, seed);
+
+    if missing > 0 {
+        return Err(format!("{} of {} outputs were not written", missing, count).into());
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    validate(&args);
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
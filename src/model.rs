@@ -0,0 +1,264 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::sync::Arc;
+
+use strum::IntoEnumIterator;
+
+use crate::cells::WaysToBecomeTile;
+use crate::patterns::Compatibility;
+use crate::rules::AdjacencyRules;
+use crate::slots::{Location, LocationTable};
+use crate::tiles::{Tile, TileId, TileTable};
+use crate::Wave;
+
+/// The tiles of a wave along with everything derived from them which does not
+/// change while collapsing, built once and shared by every wave of the model
+/// through an [`Arc`].
+#[derive(Debug)]
+pub struct Model<Data> {
+    tiles: Box<[Tile<Data>]>,
+    /// for every tile the tiles which may be placed next to it in each
+    /// direction
+    propagator: AdjacencyRules,
+    /// the ways to become each tile of a cell which has not lost any tiles
+    ways_to_become_tile: TileTable<WaysToBecomeTile>,
+    /// the tiles which no tile allows next to them in each direction
+    unsupported: LocationTable<Vec<TileId>>,
+    compatibility: Compatibility,
+    size: usize,
+    sum_weights: f64,
+    sum_weight_log_weight: f64,
+}
+
+impl<Data: PartialEq> Model<Data> {
+    /// Derives which tiles may be adjacent by comparing their patterns.
+    pub fn new(tiles: Box<[Tile<Data>]>, compatibility: Compatibility) -> Result<Self, ModelError> {
+        // checked before comparing every pair of tiles
+        Self::check_tiles(&tiles)?;
+        let rules = AdjacencyRules::from_tiles(&tiles, compatibility);
+
+        Self::build(tiles, rules, compatibility)
+    }
+}
+
+impl<Data> Model<Data> {
+    /// Creates a model whose adjacency is given by `rules` rather than derived
+    /// from the data of the tiles, see [`crate::tiled::TiledModel`].
+    ///
+    /// The cells are rendered as whole tiles next to each other.
+    pub fn from_rules(tiles: Box<[Tile<Data>]>, rules: AdjacencyRules) -> Result<Self, ModelError> {
+        Self::build(tiles, rules, Compatibility::Edge)
+    }
+
+    /// The most tiles a model can have, as the ways to become a tile are
+    /// counted in a u16.
+    pub const MAX_TILES: usize = u16::MAX as usize;
+
+    fn check_tiles(tiles: &[Tile<Data>]) -> Result<(), ModelError> {
+        if tiles.is_empty() {
+            return Err(ModelError::NoTiles);
+        }
+        if tiles.len() > Self::MAX_TILES {
+            return Err(ModelError::TooManyTiles(tiles.len()));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn build(
+        tiles: Box<[Tile<Data>]>,
+        rules: AdjacencyRules,
+        compatibility: Compatibility,
+    ) -> Result<Self, ModelError> {
+        assert_eq!(tiles.len(), rules.num_tiles());
+        Self::check_tiles(&tiles)?;
+
+        let mut ways_to_become_tile: TileTable<WaysToBecomeTile> =
+            TileTable(vec![WaysToBecomeTile::default(); tiles.len()].into_boxed_slice());
+        let mut unsupported: LocationTable<Vec<TileId>> = LocationTable::default();
+
+        for tile in tiles.iter() {
+            for location in Location::iter() {
+                ways_to_become_tile[tile][location] = rules.allowed(tile.id, location).len() as u16;
+
+                if ways_to_become_tile[tile][location] == 0 {
+                    unsupported[location].push(tile.id);
+                }
+            }
+        }
+
+        let size = tiles[0].pattern().size();
+        let probs = tiles.iter().map(|t| t.probability);
+        let sum_weights: f64 = probs.clone().sum();
+        let sum_weight_log_weight: f64 = probs.map(|p| p * p.log(2.0)).sum();
+
+        Ok(Self {
+            tiles,
+            propagator: rules,
+            ways_to_become_tile,
+            unsupported,
+            compatibility,
+            size,
+            sum_weights,
+            sum_weight_log_weight,
+        })
+    }
+
+    /// Creates an uncollapsed wave of the model, see [`Wave::new`].
+    pub fn wave(self: &Arc<Self>, x_cells: usize, y_cells: usize) -> Wave<Data> {
+        Wave::new(Arc::clone(self), x_cells, y_cells)
+    }
+
+    pub fn tiles(&self) -> &[Tile<Data>] {
+        &self.tiles
+    }
+
+    pub fn tile(&self, id: TileId) -> &Tile<Data> {
+        &self.tiles[*id]
+    }
+
+    /// The tiles which may be placed at `location` of `tile`.
+    pub fn allowed(&self, tile: TileId, location: Location) -> &[TileId] {
+        self.propagator.allowed(tile, location)
+    }
+
+    /// Which tiles may be adjacent in each direction.
+    pub fn rules(&self) -> &AdjacencyRules {
+        &self.propagator
+    }
+
+    pub fn compatibility(&self) -> Compatibility {
+        self.compatibility
+    }
+
+    /// The width and height of the patterns of the tiles.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub(crate) fn ways_to_become_tile(&self) -> &TileTable<WaysToBecomeTile> {
+        &self.ways_to_become_tile
+    }
+
+    /// The tiles which can not be placed in a cell with a neighbor at
+    /// `location`.
+    pub(crate) fn unsupported(&self, location: Location) -> &[TileId] {
+        &self.unsupported[location]
+    }
+
+    pub(crate) fn sum_weights(&self) -> f64 {
+        self.sum_weights
+    }
+
+    pub(crate) fn sum_weight_log_weight(&self) -> f64 {
+        self.sum_weight_log_weight
+    }
+}
+
+/// Why a model could not be built from its tiles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModelError {
+    /// No tiles to fill the cells of a wave with.
+    NoTiles,
+    /// More than [`Model::MAX_TILES`] tiles.
+    TooManyTiles(usize),
+}
+
+impl Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::NoTiles => write!(f, "no tiles"),
+            ModelError::TooManyTiles(num_tiles) => write!(
+                f,
+                "{} tiles, at most {} are supported",
+                num_tiles,
+                Model::<()>::MAX_TILES
+            ),
+        }
+    }
+}
+
+impl Error for ModelError {}
+
+#[cfg(test)]
+mod test {
+    use strum::IntoEnumIterator;
+
+    use super::*;
+    use crate::patterns::Pattern;
+    use crate::tiled::TiledModel;
+
+    #[test]
+    fn support_counts_match_rules() {
+        let mut tiled = TiledModel::new();
+        let a = tiled.add_tile(Pattern::new(vec!['a'].into_boxed_slice()), 1.0);
+        let b = tiled.add_tile(Pattern::new(vec!['b'].into_boxed_slice()), 3.0);
+        tiled.allow(a, Location::East, b);
+        tiled.allow(b, Location::East, b);
+        tiled.allow(a, Location::North, a);
+
+        let model = tiled.build().unwrap();
+
+        for tile in model.tiles() {
+            for location in Location::iter() {
+                assert_eq!(
+                    model.ways_to_become_tile()[tile][location] as usize,
+                    model.allowed(tile.id(), location).len()
+                );
+            }
+        }
+        assert_eq!(model.sum_weights(), 4.0);
+    }
+
+    #[test]
+    fn stripes_continue_across_edges() {
+        #[rustfmt::skip]
+        let stripes = Pattern::new(vec![
+            1, 1, 1,
+            2, 2, 2,
+            3, 3, 3,
+        ].into_boxed_slice());
+        let model = Model::new(
+            vec![Tile::new(stripes, 1.0, 0usize)].into_boxed_slice(),
+            Compatibility::Edge,
+        )
+        .unwrap();
+
+        assert_eq!(
+            model.allowed(TileId::from(0usize), Location::East),
+            [TileId::from(0usize)]
+        );
+        assert_eq!(
+            model.allowed(TileId::from(0usize), Location::West),
+            [TileId::from(0usize)]
+        );
+        assert!(model
+            .allowed(TileId::from(0usize), Location::North)
+            .is_empty());
+    }
+
+    #[test]
+    fn empty_models_are_rejected() {
+        assert_eq!(
+            TiledModel::<char>::new().build().unwrap_err(),
+            ModelError::NoTiles
+        );
+        assert_eq!(
+            Model::<char>::new(Box::new([]), Compatibility::Overlapping).unwrap_err(),
+            ModelError::NoTiles
+        );
+    }
+
+    #[test]
+    fn too_many_tiles_are_rejected() {
+        let num_tiles = Model::<usize>::MAX_TILES + 1;
+        let tiles: Box<_> = (0..num_tiles)
+            .map(|i| Tile::new(Pattern::new(vec![i].into_boxed_slice()), 1.0, i))
+            .collect();
+
+        assert_eq!(
+            Model::new(tiles, Compatibility::Overlapping).unwrap_err(),
+            ModelError::TooManyTiles(num_tiles)
+        );
+    }
+}
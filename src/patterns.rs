@@ -1,8 +1,28 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
 use integer_sqrt::IntegerSquareRoot;
 
 use crate::slots::{Location, Slot};
+use crate::tiles::Tile;
+use crate::Periodicity;
+
+/// The rule used to decide whether two patterns may be placed next to each
+/// other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Compatibility {
+    /// Only the touching edges (slots) of the two patterns must match, as in
+    /// an edge matched tileset.
+    Edge,
+    /// The patterns must agree on the (N - 1) x N region they share when one
+    /// is shifted by a single cell onto the other, as in the overlapping
+    /// model of the reference implementation.
+    Overlapping,
+}
 
-#[derive(Debug, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pattern<Data> {
     data: Box<[Data]>,
     size: usize,
@@ -19,7 +39,7 @@ impl<Data> Pattern<Data> {
         }
     }
 
-    fn slot(&self, location: Location) -> Slot<Data> {
+    fn slot(&self, location: Location) -> Slot<'_, Data> {
         match location {
             Location::North => {
                 Slot::new(self.data.iter().take(self.size).collect(), Location::North)
@@ -43,19 +63,69 @@ impl<Data> Pattern<Data> {
         }
     }
 
-    pub fn data(&self) -> &Box<[Data]> {
+    pub fn data(&self) -> &[Data] {
         &self.data
     }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Converts every element of the pattern, keeping its layout.
+    pub fn map<T>(self, f: impl FnMut(Data) -> T) -> Pattern<T> {
+        Pattern {
+            data: self.data.into_vec().into_iter().map(f).collect(),
+            size: self.size,
+        }
+    }
 }
 
 impl<Data: PartialEq> Pattern<Data> {
-    pub(crate) fn is_compatible(&self, b: &Pattern<Data>, b_location: Location) -> bool {
-        self.slot(b_location)
-            .can_be_adjacent(&b.slot(b_location.opposite()))
+    pub(crate) fn is_compatible(
+        &self,
+        b: &Pattern<Data>,
+        b_location: Location,
+        compatibility: Compatibility,
+    ) -> bool {
+        match compatibility {
+            Compatibility::Edge => self
+                .slot(b_location)
+                .can_be_adjacent(&b.slot(b_location.opposite())),
+            Compatibility::Overlapping => self.overlaps(b, b_location),
+        }
+    }
+
+    /// checks that every element of `self` which is also covered by `b`, when
+    /// `b` is shifted one cell towards `b_location`, is equal to the element
+    /// of `b` in that position
+    fn overlaps(&self, b: &Pattern<Data>, b_location: Location) -> bool {
+        if self.size != b.size {
+            return false;
+        }
+
+        let (dx, dy): (isize, isize) = match b_location {
+            Location::North => (0, -1),
+            Location::East => (1, 0),
+            Location::South => (0, 1),
+            Location::West => (-1, 0),
+        };
+
+        let size = self.size as isize;
+        let x_range = dx.max(0)..(size + dx).min(size);
+        let y_range = dy.max(0)..(size + dy).min(size);
+
+        y_range
+            .flat_map(|y| x_range.clone().map(move |x| (x, y)))
+            .all(|(x, y)| {
+                let a_index = y * size + x;
+                let b_index = (y - dy) * size + (x - dx);
+
+                self.data[a_index as usize] == b.data[b_index as usize]
+            })
     }
 }
 
-impl<Data: Clone + Default> Pattern<Data> {
+impl<Data: Clone> Pattern<Data> {
     pub fn all_permutations(self) -> [Self; 8] {
         let pattern = self;
         let reflected = pattern.reflect();
@@ -81,11 +151,7 @@ impl<Data: Clone + Default> Pattern<Data> {
     /// clockwise 90 degree rotation
     pub fn rotate(&self) -> Self {
         Self {
-            data: self.apply(|row, col, rotated| {
-                let new_col = (self.size - 1) - row;
-                let new_row = col;
-                rotated[new_row * self.size + new_col] = self.data[row * self.size + col].clone();
-            }),
+            data: self.apply(|row, col| (self.size - 1 - col) * self.size + row),
             size: self.size,
         }
     }
@@ -93,29 +159,310 @@ impl<Data: Clone + Default> Pattern<Data> {
     /// y axis reflection
     pub fn reflect(&self) -> Self {
         Self {
-            data: self.apply(|row, col, reflected| {
-                reflected[row * self.size + col] =
-                    self.data[row * self.size + self.size - 1 - col].clone()
-            }),
+            data: self.apply(|row, col| row * self.size + self.size - 1 - col),
             size: self.size,
         }
     }
 
+    /// builds a pattern of the same size where `f` gives the index of the
+    /// element which is moved to each row and column
     fn apply<F>(&self, f: F) -> Box<[Data]>
     where
-        F: Fn(usize, usize, &mut [Data]),
+        F: Fn(usize, usize) -> usize,
     {
-        let mut out_data = vec![Data::default(); self.data.len()];
+        (0..self.size)
+            .flat_map(|row| (0..self.size).map(move |col| (row, col)))
+            .map(|(row, col)| self.data[f(row, col)].clone())
+            .collect()
+    }
+}
+
+/// Counts how often each distinct pattern occurs.
+///
+/// Patterns are ordered by when they were first inserted, so the tiles
+/// created from the same sequence of patterns always have the same
+/// [`crate::tiles::TileId`]s no matter how the patterns hash.
+#[derive(Debug, Clone)]
+pub struct PatternSet<Data> {
+    /// the position the pattern was first seen at and its count
+    patterns: HashMap<Pattern<Data>, (usize, usize)>,
+    total: usize,
+}
+
+impl<Data> Default for PatternSet<Data> {
+    fn default() -> Self {
+        Self {
+            patterns: HashMap::new(),
+            total: 0,
+        }
+    }
+}
+
+impl<Data: Hash + Eq> PatternSet<Data> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, pattern: Pattern<Data>) {
+        let next = self.patterns.len();
+        self.patterns.entry(pattern).or_insert((next, 0)).1 += 1;
+        self.total += 1;
+    }
+
+    /// the number of distinct patterns
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// the number of patterns inserted counting duplicates
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// the distinct patterns with their counts in the order they were first
+    /// inserted
+    pub fn iter(&self) -> impl Iterator<Item = (&Pattern<Data>, usize)> {
+        let mut patterns: Vec<_> = self.patterns.iter().collect();
+        patterns.sort_unstable_by_key(|(_, (index, _))| *index);
 
-        for row in 0..self.size {
-            for col in 0..self.size {
-                f(row, col, &mut out_data)
+        patterns
+            .into_iter()
+            .map(|(pattern, (_, count))| (pattern, *count))
+    }
+
+    /// Creates a tile for every distinct pattern, with a probability
+    /// proportional to how often it occured, whose id is the order the pattern
+    /// was first inserted.
+    pub fn into_tiles(self) -> Box<[Tile<Data>]> {
+        let total = self.total as f64;
+        let mut patterns: Vec<_> = self.patterns.into_iter().collect();
+        patterns.sort_unstable_by_key(|(_, (index, _))| *index);
+
+        patterns
+            .into_iter()
+            .map(|(pattern, (index, count))| Tile::new(pattern, count as f64 / total, index))
+            .collect()
+    }
+}
+
+impl<Data: Hash + Eq> Extend<Pattern<Data>> for PatternSet<Data> {
+    fn extend<T: IntoIterator<Item = Pattern<Data>>>(&mut self, iter: T) {
+        iter.into_iter().for_each(|p| self.insert(p));
+    }
+}
+
+impl<Data: Hash + Eq> FromIterator<Pattern<Data>> for PatternSet<Data> {
+    fn from_iter<T: IntoIterator<Item = Pattern<Data>>>(iter: T) -> Self {
+        let mut set = Self::new();
+        set.extend(iter);
+        set
+    }
+}
+
+/// Which rotations and reflections of the patterns of an input are used as
+/// well, patterns of side views or text should usually not be rotated.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Symmetry {
+    /// add the pattern rotated by 90, 180 and 270 degrees
+    pub rotate: bool,
+    /// add the reflection of every pattern
+    pub reflect: bool,
+}
+
+impl Symmetry {
+    /// only the patterns as they occur in the input
+    pub const NONE: Self = Self {
+        rotate: false,
+        reflect: false,
+    };
+    /// the patterns and their reflections
+    pub const REFLECT: Self = Self {
+        rotate: false,
+        reflect: true,
+    };
+    /// the four rotations of the patterns
+    pub const ROTATE: Self = Self {
+        rotate: true,
+        reflect: false,
+    };
+    /// every rotation and reflection of the patterns
+    pub const ALL: Self = Self {
+        rotate: true,
+        reflect: true,
+    };
+
+    /// The symmetry producing 1, 2, 4 or 8 variants of each pattern.
+    pub fn from_variants(variants: usize) -> Option<Self> {
+        match variants {
+            1 => Some(Self::NONE),
+            2 => Some(Self::REFLECT),
+            4 => Some(Self::ROTATE),
+            8 => Some(Self::ALL),
+            _ => None,
+        }
+    }
+
+    /// The number of variants of each pattern.
+    pub fn variants(&self) -> usize {
+        let rotations = if self.rotate { 4 } else { 1 };
+        let reflections = if self.reflect { 2 } else { 1 };
+
+        rotations * reflections
+    }
+
+    /// The pattern followed by its variants, in the same order as
+    /// [`Pattern::all_permutations`].
+    pub fn apply<Data: Clone>(&self, pattern: Pattern<Data>) -> Vec<Pattern<Data>> {
+        let mut variants = Vec::with_capacity(self.variants());
+        variants.push(pattern);
+
+        while variants.len() < self.variants() {
+            let last = variants.len() - 1;
+
+            if self.reflect && last % 2 == 0 {
+                variants.push(variants[last].reflect());
+            } else {
+                // every rotation follows the one before, skipping over its
+                // reflection
+                let previous = if self.reflect { last - 1 } else { last };
+                variants.push(variants[previous].rotate());
             }
         }
-        out_data.into_boxed_slice()
+
+        variants
     }
 }
 
+/// A two dimensional grid of data, such as an image or the lines of a text,
+/// patterns can be extracted from.
+pub trait Grid {
+    type Data;
+
+    fn width(&self) -> usize;
+
+    fn height(&self) -> usize;
+
+    /// the element in column `x` of row `y`
+    fn get(&self, x: usize, y: usize) -> Self::Data;
+}
+
+/// The rows of the grid, every row must be the same length.
+impl<T: Clone> Grid for [Vec<T>] {
+    type Data = T;
+
+    fn width(&self) -> usize {
+        self.first().map_or(0, |row| row.len())
+    }
+
+    fn height(&self) -> usize {
+        self.len()
+    }
+
+    fn get(&self, x: usize, y: usize) -> T {
+        self[y][x].clone()
+    }
+}
+
+impl<T: Clone> Grid for Vec<Vec<T>> {
+    type Data = T;
+
+    fn width(&self) -> usize {
+        self[..].width()
+    }
+
+    fn height(&self) -> usize {
+        self.len()
+    }
+
+    fn get(&self, x: usize, y: usize) -> T {
+        self[y][x].clone()
+    }
+}
+
+impl<P: image::Pixel> Grid for image::ImageBuffer<P, Vec<P::Subpixel>> {
+    type Data = P;
+
+    fn width(&self) -> usize {
+        self.width() as usize
+    }
+
+    fn height(&self) -> usize {
+        self.height() as usize
+    }
+
+    fn get(&self, x: usize, y: usize) -> P {
+        *self.get_pixel(x as u32, y as u32)
+    }
+}
+
+/// Counts every `size` x `size` window of `grid`, along with the variants
+/// given by `symmetry`.
+///
+/// Along the axes `periodicity` wraps around windows continue on the opposite
+/// edge, along the others windows which would leave the grid are skipped.
+///
+/// Panics if `size` is zero, as empty patterns can not be compared.
+pub fn extract_patterns<G>(
+    grid: &G,
+    size: usize,
+    symmetry: Symmetry,
+    periodicity: Periodicity,
+) -> PatternSet<G::Data>
+where
+    G: Grid + ?Sized,
+    G::Data: Clone + Hash + Eq,
+{
+    assert!(size > 0, "patterns must be at least 1 element wide");
+    let (width, height) = (grid.width(), grid.height());
+
+    let x_windows = if periodicity.wraps_horizontally() {
+        width
+    } else {
+        (width + 1).saturating_sub(size)
+    };
+    let y_windows = if periodicity.wraps_vertically() {
+        height
+    } else {
+        (height + 1).saturating_sub(size)
+    };
+
+    let mut patterns = PatternSet::new();
+
+    for y in 0..y_windows {
+        for x in 0..x_windows {
+            let mut data = Vec::with_capacity(size * size);
+            for j in 0..size {
+                for i in 0..size {
+                    data.push(grid.get((x + i) % width, (y + j) % height));
+                }
+            }
+
+            patterns.extend(symmetry.apply(Pattern::new(data.into_boxed_slice())));
+        }
+    }
+
+    patterns
+}
+
+/// Creates the tiles of every distinct pattern of `grid`, weighted by how
+/// often each occurs, see [`extract_patterns`].
+pub fn extract<G>(
+    grid: &G,
+    size: usize,
+    symmetry: Symmetry,
+    periodicity: Periodicity,
+) -> Box<[Tile<G::Data>]>
+where
+    G: Grid + ?Sized,
+    G::Data: Clone + Hash + Eq,
+{
+    extract_patterns(grid, size, symmetry, periodicity).into_tiles()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -139,7 +486,7 @@ mod test {
 
         let pattern = Pattern::new(data.into_boxed_slice());
 
-        assert_eq!(&expected.into_boxed_slice(), pattern.rotate().data());
+        assert_eq!(&expected[..], pattern.rotate().data());
 
         #[rustfmt::skip]
         let data: Vec<usize> = vec![
@@ -159,7 +506,7 @@ mod test {
 
         let pattern = Pattern::new(data.into_boxed_slice());
 
-        assert_eq!(&expected.into_boxed_slice(), pattern.rotate().data());
+        assert_eq!(&expected[..], pattern.rotate().data());
     }
 
     #[test]
@@ -180,7 +527,7 @@ mod test {
 
         let pattern = Pattern::new(data.into_boxed_slice());
 
-        assert_eq!(&expected.into_boxed_slice(), pattern.reflect().data());
+        assert_eq!(&expected[..], pattern.reflect().data());
 
         #[rustfmt::skip]
         let data: Vec<usize> = vec![
@@ -200,7 +547,7 @@ mod test {
 
         let pattern = Pattern::new(data.into_boxed_slice());
 
-        assert_eq!(&expected.into_boxed_slice(), pattern.reflect().data());
+        assert_eq!(&expected[..], pattern.reflect().data());
     }
 
     #[test]
@@ -224,7 +571,10 @@ mod test {
 
         let slot = pattern.slot(Location::North);
 
-        assert_eq!(*slot.data(), slots[Location::North].iter().collect())
+        assert_eq!(
+            slot.data().to_vec(),
+            slots[Location::North].iter().collect::<Vec<_>>()
+        )
     }
 
     #[test]
@@ -248,7 +598,10 @@ mod test {
 
         let slot = pattern.slot(Location::East);
 
-        assert_eq!(*slot.data(), slots[Location::East].iter().collect())
+        assert_eq!(
+            slot.data().to_vec(),
+            slots[Location::East].iter().collect::<Vec<_>>()
+        )
     }
 
     #[test]
@@ -272,7 +625,10 @@ mod test {
 
         let slot = pattern.slot(Location::South);
 
-        assert_eq!(*slot.data(), slots[Location::South].iter().collect())
+        assert_eq!(
+            slot.data().to_vec(),
+            slots[Location::South].iter().collect::<Vec<_>>()
+        )
     }
 
     #[test]
@@ -296,17 +652,184 @@ mod test {
 
         let slot = pattern.slot(Location::West);
 
-        assert_eq!(*slot.data(), slots[Location::West].iter().collect())
+        assert_eq!(
+            slot.data().to_vec(),
+            slots[Location::West].iter().collect::<Vec<_>>()
+        )
     }
 
     #[test]
     fn is_compatible() {
         let a = Pattern::new((1..=9usize).collect());
-        let b = a.rotate().rotate();
+        let b = Pattern::new((7..=15usize).collect());
 
         dbg!(a.slot(Location::South));
         dbg!(b.slot(Location::North));
 
-        assert!(a.is_compatible(&b, Location::South));
+        assert!(a.is_compatible(&b, Location::South, Compatibility::Edge));
+        assert!(b.is_compatible(&a, Location::North, Compatibility::Edge));
+        // the bottom row of `a` reversed
+        assert!(!a.is_compatible(&a.rotate().rotate(), Location::South, Compatibility::Edge));
+
+        #[rustfmt::skip]
+        let stripes = Pattern::new(vec![
+            1, 1, 1,
+            2, 2, 2,
+            3, 3, 3,
+        ].into_boxed_slice());
+
+        assert!(stripes.is_compatible(&stripes, Location::East, Compatibility::Edge));
+        assert!(stripes.is_compatible(&stripes, Location::West, Compatibility::Edge));
+        assert!(!stripes.is_compatible(&stripes, Location::South, Compatibility::Edge));
+    }
+
+    #[test]
+    fn overlapping_is_compatible() {
+        #[rustfmt::skip]
+        let a = Pattern::new(vec![
+            1, 2, 3,
+            4, 5, 6,
+            7, 8, 9,
+        ].into_boxed_slice());
+
+        #[rustfmt::skip]
+        let east = Pattern::new(vec![
+            2, 3, 0,
+            5, 6, 0,
+            8, 9, 0,
+        ].into_boxed_slice());
+
+        #[rustfmt::skip]
+        let south = Pattern::new(vec![
+            4, 5, 6,
+            7, 8, 9,
+            0, 0, 0,
+        ].into_boxed_slice());
+
+        assert!(a.is_compatible(&east, Location::East, Compatibility::Overlapping));
+        assert!(east.is_compatible(&a, Location::West, Compatibility::Overlapping));
+        assert!(a.is_compatible(&south, Location::South, Compatibility::Overlapping));
+        assert!(south.is_compatible(&a, Location::North, Compatibility::Overlapping));
+
+        assert!(!a.is_compatible(&east, Location::West, Compatibility::Overlapping));
+        assert!(!a.is_compatible(&east, Location::South, Compatibility::Overlapping));
+        assert!(!a.is_compatible(&south, Location::North, Compatibility::Overlapping));
+        assert!(!a.is_compatible(&south, Location::East, Compatibility::Overlapping));
+    }
+
+    #[test]
+    fn overlapping_differs_from_edge() {
+        let a = Pattern::new(vec![1usize; 9].into_boxed_slice());
+
+        #[rustfmt::skip]
+        let b = Pattern::new(vec![
+            1, 2, 1,
+            1, 2, 1,
+            1, 2, 1,
+        ].into_boxed_slice());
+
+        // the touching edges agree but the middle column of `b` would overlap
+        // the right column of `a`
+        assert!(a.is_compatible(&b, Location::East, Compatibility::Edge));
+        assert!(!a.is_compatible(&b, Location::East, Compatibility::Overlapping));
+    }
+
+    #[test]
+    fn pattern_set_keeps_first_seen_order() {
+        let patterns = [3usize, 1, 3, 2, 1, 3]
+            .into_iter()
+            .map(|i| Pattern::new(vec![i; 4].into_boxed_slice()));
+        let set: PatternSet<_> = patterns.collect();
+
+        assert_eq!(set.len(), 3);
+        assert_eq!(set.total(), 6);
+        assert_eq!(
+            set.iter()
+                .map(|(p, count)| (p.data()[0], count))
+                .collect::<Vec<_>>(),
+            vec![(3, 3), (1, 2), (2, 1)]
+        );
+
+        let tiles = set.into_tiles();
+        assert_eq!(
+            tiles
+                .iter()
+                .map(|t| (*t.id(), t.data()[0]))
+                .collect::<Vec<_>>(),
+            vec![(0, 3), (1, 1), (2, 2)]
+        );
+        assert_eq!(tiles[0].probability, 0.5);
+    }
+
+    #[test]
+    fn extract_skips_windows_leaving_bounded_input() {
+        let input = vec![vec!['a', 'b', 'c'], vec!['d', 'e', 'f']];
+
+        let patterns = extract_patterns(&input, 2, Symmetry::ALL, Periodicity::Neither);
+        assert_eq!(patterns.total(), 2 * 8);
+        let (first, _) = patterns.iter().next().unwrap();
+        assert_eq!(first.data(), &['a', 'b', 'd', 'e']);
+        // no window pairs the right edge with the left
+        assert!(patterns
+            .iter()
+            .all(|(p, _)| !p.data().contains(&'c') || !p.data().contains(&'a')));
+
+        let total = |periodicity| extract_patterns(&input, 2, Symmetry::ALL, periodicity).total();
+        assert_eq!(total(Periodicity::Horizontal), 3 * 8);
+        assert_eq!(total(Periodicity::Vertical), 2 * 2 * 8);
+        assert_eq!(total(Periodicity::Both), 6 * 8);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 1 element wide")]
+    fn extract_rejects_empty_patterns() {
+        let input = vec![vec!['a', 'b'], vec!['c', 'd']];
+        extract(&input, 0, Symmetry::ALL, Periodicity::Neither);
+    }
+
+    #[test]
+    fn symmetry_variants() {
+        let pattern = Pattern::new(vec![1, 2, 3, 4].into_boxed_slice());
+
+        assert_eq!(
+            Symmetry::ALL.apply(pattern.clone()),
+            pattern.clone().all_permutations()
+        );
+        assert_eq!(Symmetry::NONE.apply(pattern.clone()), vec![pattern.clone()]);
+        assert_eq!(
+            Symmetry::REFLECT.apply(pattern.clone()),
+            vec![pattern.clone(), pattern.reflect()]
+        );
+        assert_eq!(
+            Symmetry::ROTATE.apply(pattern.clone()),
+            vec![
+                pattern.clone(),
+                pattern.rotate(),
+                pattern.rotate().rotate(),
+                pattern.rotate().rotate().rotate()
+            ]
+        );
+
+        for variants in [1, 2, 4, 8] {
+            assert_eq!(
+                Symmetry::from_variants(variants).unwrap().variants(),
+                variants
+            );
+        }
+        assert_eq!(Symmetry::from_variants(3), None);
+    }
+
+    #[test]
+    fn extract_without_symmetry_keeps_orientation() {
+        // a side view where the ground is always below the sky
+        let input = vec![vec!['s', 's', 's'], vec!['g', 'g', 'g']];
+
+        let tiles = extract(&input, 2, Symmetry::NONE, Periodicity::Horizontal);
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0].data(), &['s', 's', 'g', 'g']);
+        assert_eq!(tiles[0].probability, 1.0);
+
+        let tiles = extract(&input, 2, Symmetry::ALL, Periodicity::Horizontal);
+        assert_eq!(tiles.len(), 4);
     }
 }
@@ -0,0 +1,151 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::Duration;
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, ImageResult, RgbaImage};
+use rand::Rng;
+
+use crate::renderers::CellRenderer;
+use crate::{Wave, WaveCollapseError};
+
+/// Images of a wave taken while it collapsed, see [`Wave::run_recorded`].
+#[derive(Debug, Default)]
+pub struct Recording {
+    pub frames: Vec<RgbaImage>,
+    /// the error the wave failed with, the last frame shows where the
+    /// contradiction formed
+    pub error: Option<WaveCollapseError>,
+}
+
+impl Recording {
+    /// Writes the frames to an animated GIF which loops forever, showing
+    /// each frame for `frame_delay`.
+    pub fn save_gif<P: AsRef<Path>>(&self, path: P, frame_delay: Duration) -> ImageResult<()> {
+        let mut encoder = GifEncoder::new(BufWriter::new(File::create(path)?));
+        encoder.set_repeat(Repeat::Infinite)?;
+
+        let delay = Delay::from_saturating_duration(frame_delay);
+        encoder.encode_frames(
+            self.frames
+                .iter()
+                .map(|frame| Frame::from_parts(frame.clone(), 0, 0, delay)),
+        )
+    }
+
+    /// Writes every frame to a numbered PNG file, `frame_0000.png` and so on,
+    /// in the directory `dir`.
+    pub fn save_png_sequence<P: AsRef<Path>>(&self, dir: P) -> ImageResult<()> {
+        for (i, frame) in self.frames.iter().enumerate() {
+            frame.save(dir.as_ref().join(format!("frame_{:04}.png", i)))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<Data, R: Rng> Wave<Data, R> {
+    /// Collapses every cell of the wave like [`Wave::run`], rendering it with
+    /// [`Wave::to_image`] before the first observation, after every `every`
+    /// observations and once it is collapsed or failed.
+    pub fn run_recorded<C: CellRenderer<Data> + ?Sized>(
+        &mut self,
+        every: usize,
+        renderer: &C,
+    ) -> Recording {
+        assert!(every > 0);
+
+        let mut recording = Recording::default();
+        recording.frames.push(self.to_image(renderer));
+
+        let mut observations = 0usize;
+        while !self.collapsed() {
+            match self.collapse() {
+                Ok(Some(_)) => {
+                    observations += 1;
+
+                    // the last frame is pushed once the loop ends
+                    if observations.is_multiple_of(every) && !self.collapsed() {
+                        recording.frames.push(self.to_image(renderer));
+                    }
+                }
+                Ok(None) => {}
+                Err(error) => {
+                    recording.error = Some(error);
+                    break;
+                }
+            }
+        }
+
+        recording.frames.push(self.to_image(renderer));
+
+        recording
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use image::Rgba;
+
+    use super::*;
+    use crate::model::Model;
+    use crate::renderers::First;
+    use crate::test_util::coloring_with;
+
+    fn coloring(num_colors: usize) -> Arc<Model<Rgba<u8>>> {
+        coloring_with(num_colors, |c| (Rgba([c as u8 * 60, 0, 0, 255]), 1.0))
+    }
+
+    #[test]
+    fn records_every_k_observations() {
+        let model = coloring(4);
+        let mut wave = model.wave(4, 4).with_seed(1).with_backtracking(100);
+
+        let recording = wave.run_recorded(3, &First);
+        assert!(recording.error.is_none());
+        assert!(wave.collapsed());
+        // the seed collapses the wave in 16 observations, so there are frames
+        // before the first, after the 3rd, 6th, 9th, 12th and 15th and once
+        // it is collapsed
+        assert_eq!(
+            model
+                .wave(4, 4)
+                .with_seed(1)
+                .with_backtracking(100)
+                .steps()
+                .count(),
+            16
+        );
+        assert_eq!(recording.frames.len(), 7);
+        assert!(recording
+            .frames
+            .iter()
+            .all(|f| f.dimensions() == recording.frames[0].dimensions()));
+
+        let path = std::env::temp_dir().join(format!("wfc-recording-{}.gif", std::process::id()));
+        recording
+            .save_gif(&path, Duration::from_millis(50))
+            .unwrap();
+        let decoded = image::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(decoded.width(), recording.frames[0].width());
+    }
+
+    #[test]
+    fn records_contradictions() {
+        let model = coloring(2);
+        let mut wave = model.wave(3, 3);
+
+        let recording = wave.run_recorded(1, &First);
+        assert!(matches!(
+            recording.error,
+            Some(WaveCollapseError::InvalidCell(_))
+        ));
+
+        let last = recording.frames.last().unwrap();
+        assert!(last.pixels().any(|p| *p == Wave::<Rgba<u8>>::CONTRADICTION));
+    }
+}
@@ -0,0 +1,135 @@
+//! Renderers for [`crate::Wave::to_image`].
+
+use image::Rgba;
+
+use crate::tiles::Tile;
+
+/// Draws a single cell of a wave.
+pub trait CellRenderer<Data> {
+    /// The colors of the pattern of the cell at `index` given its remaining
+    /// tiles, of which there is always at least one, in rows of the size of
+    /// the patterns.
+    fn render(&self, index: usize, tiles: &[&Tile<Data>]) -> Box<[Rgba<u8>]>;
+}
+
+impl<Data, F> CellRenderer<Data> for F
+where
+    F: Fn(usize, &[&Tile<Data>]) -> Box<[Rgba<u8>]>,
+{
+    fn render(&self, index: usize, tiles: &[&Tile<Data>]) -> Box<[Rgba<u8>]> {
+        self(index, tiles)
+    }
+}
+
+/// The mean color of the remaining tiles weighted by their probabilities,
+/// which shows how likely each color is while the wave is collapsing.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WeightedMean;
+
+impl<Data: Clone + Into<Rgba<u8>>> CellRenderer<Data> for WeightedMean {
+    fn render(&self, _index: usize, tiles: &[&Tile<Data>]) -> Box<[Rgba<u8>]> {
+        let len = tiles.first().map_or(0, |tile| tile.data().len());
+        let mut sums = vec![[0.0; 4]; len];
+        let mut total = 0.0;
+
+        for tile in tiles {
+            for (sum, data) in sums.iter_mut().zip(tile.data()) {
+                let Rgba(channels) = data.clone().into();
+                for (sum, channel) in sum.iter_mut().zip(channels) {
+                    *sum += channel as f64 * tile.probability();
+                }
+            }
+
+            total += tile.probability();
+        }
+
+        sums.into_iter()
+            .map(|sum| Rgba(sum.map(|s| (s / total).round() as u8)))
+            .collect()
+    }
+}
+
+/// The first of the remaining tiles.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct First;
+
+impl<Data: Clone + Into<Rgba<u8>>> CellRenderer<Data> for First {
+    fn render(&self, _index: usize, tiles: &[&Tile<Data>]) -> Box<[Rgba<u8>]> {
+        to_colors(tiles[0])
+    }
+}
+
+/// The remaining tile with the highest probability, the first of them if
+/// several are equally likely.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MostLikely;
+
+impl<Data: Clone + Into<Rgba<u8>>> CellRenderer<Data> for MostLikely {
+    fn render(&self, _index: usize, tiles: &[&Tile<Data>]) -> Box<[Rgba<u8>]> {
+        let most_likely = tiles
+            .iter()
+            .reduce(|best, tile| {
+                if tile.probability() > best.probability() {
+                    tile
+                } else {
+                    best
+                }
+            })
+            .expect("a cell with tiles is rendered");
+
+        to_colors(most_likely)
+    }
+}
+
+fn to_colors<Data: Clone + Into<Rgba<u8>>>(tile: &Tile<Data>) -> Box<[Rgba<u8>]> {
+    tile.data().iter().map(|data| data.clone().into()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::patterns::Pattern;
+
+    fn tile(color: [u8; 4], probability: f64, id: usize) -> Tile<Rgba<u8>> {
+        Tile::new(
+            Pattern::new(vec![Rgba(color)].into_boxed_slice()),
+            probability,
+            id,
+        )
+    }
+
+    #[test]
+    fn blends() {
+        let dark = tile([0, 0, 0, 255], 3.0, 0usize);
+        let orange = tile([200, 100, 40, 255], 1.0, 1usize);
+        let tiles = [&dark, &orange];
+
+        assert_eq!(&*WeightedMean.render(0, &tiles), &[Rgba([50, 25, 10, 255])]);
+        assert_eq!(&*First.render(0, &tiles), &[Rgba([0, 0, 0, 255])]);
+        assert_eq!(
+            &*MostLikely.render(0, &[&orange, &dark]),
+            &[Rgba([0, 0, 0, 255])]
+        );
+    }
+
+    #[test]
+    fn closures_render_with_captured_state() {
+        // a palette indexed by tile id, with the uncollapsed cells in gray
+        let palette = [Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255])];
+        let renderer = |_index: usize, tiles: &[&Tile<char>]| -> Box<[Rgba<u8>]> {
+            match tiles {
+                [tile] => Box::new([palette[*tile.id()]]),
+                _ => Box::new([Rgba([128, 128, 128, 255])]),
+            }
+        };
+
+        let a = Tile::new(Pattern::new(vec!['a'].into_boxed_slice()), 1.0, 0usize);
+        let b = Tile::new(Pattern::new(vec!['b'].into_boxed_slice()), 1.0, 1usize);
+
+        assert_eq!(&*renderer.render(0, &[&b]), &[palette[1]]);
+        assert_eq!(
+            &*renderer.render(0, &[&a, &b]),
+            &[Rgba([128, 128, 128, 255])]
+        );
+    }
+}
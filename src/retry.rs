@@ -0,0 +1,112 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+
+use crate::WaveCollapseError;
+
+/// A single attempt at collapsing a wave which failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailedAttempt {
+    pub attempt: usize,
+    /// the cell which was left without any tiles
+    pub cell_index: usize,
+}
+
+/// The attempts made by [`crate::Wave::run_with_retries`].
+#[derive(Debug, Default, Clone)]
+pub struct RetryReport {
+    pub attempts: usize,
+    pub failures: Vec<FailedAttempt>,
+}
+
+impl RetryReport {
+    pub(crate) fn failed(&mut self, error: WaveCollapseError) {
+        let cell_index = match error {
+            WaveCollapseError::InvalidCell(i)
+            | WaveCollapseError::OutOfBacktracks(i)
+            | WaveCollapseError::Unsatisfiable(i) => i,
+            // a wave is never collapsed again after it finished
+            WaveCollapseError::AlreadyCollapsed => unreachable!(),
+        };
+
+        self.failures.push(FailedAttempt {
+            attempt: self.attempts - 1,
+            cell_index,
+        });
+    }
+}
+
+/// Derives an independent seed for `attempt` from `seed`, so every attempt of
+/// a seeded run can be reproduced on its own.
+pub fn derive_seed(seed: u64, attempt: usize) -> u64 {
+    // splitmix64 finalizer
+    let mut z = seed.wrapping_add((attempt as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl Display for RetryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} attempts failed",
+            self.failures.len(),
+            self.attempts
+        )
+    }
+}
+
+impl Error for RetryReport {}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use strum::IntoEnumIterator;
+
+    use crate::patterns::Pattern;
+    use crate::slots::Location;
+    use crate::test_util::coloring;
+    use crate::tiled::TiledModel;
+    use crate::Wave;
+
+    #[test]
+    fn reports_failed_attempts() {
+        let model = coloring(2);
+        let report = Wave::run_with_retries(3, |_| model.wave(3, 3)).unwrap_err();
+
+        assert_eq!(report.attempts, 3);
+        assert_eq!(report.failures.len(), 3);
+        assert_eq!(
+            report
+                .failures
+                .iter()
+                .map(|f| f.attempt)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        assert!(report.failures.iter().all(|f| f.cell_index < 9));
+    }
+
+    #[test]
+    fn stops_after_success() {
+        let mut model = TiledModel::new();
+        let a = model.add_tile(Pattern::new(vec![0].into_boxed_slice()), 1.0);
+        for location in Location::iter() {
+            model.allow(a, location, a);
+        }
+
+        let model = Arc::new(model.build().unwrap());
+        let mut built = Vec::new();
+        let (wave, report) = Wave::run_with_retries(3, |attempt| {
+            built.push(attempt);
+            model.wave(4, 4)
+        })
+        .unwrap();
+
+        assert!(wave.collapsed());
+        assert_eq!(report.attempts, 1);
+        assert!(report.failures.is_empty());
+        assert_eq!(built, vec![0]);
+    }
+}
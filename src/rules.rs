@@ -0,0 +1,71 @@
+use strum::IntoEnumIterator;
+
+use crate::patterns::Compatibility;
+use crate::slots::{Location, LocationTable};
+use crate::tiles::{Tile, TileId, TileTable};
+
+/// Which tiles may be placed next to each other, for every tile and
+/// direction.
+///
+/// Rules are always kept symmetric, allowing `neighbor` to the `location` of
+/// `tile` also allows `tile` to the opposite location of `neighbor`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AdjacencyRules {
+    allowed: TileTable<LocationTable<Vec<TileId>>>,
+}
+
+impl AdjacencyRules {
+    /// Creates rules for `num_tiles` tiles where nothing may be adjacent.
+    pub fn new(num_tiles: usize) -> Self {
+        Self {
+            allowed: TileTable((0..num_tiles).map(|_| LocationTable::default()).collect()),
+        }
+    }
+
+    /// Derives the rules by comparing the patterns of every pair of tiles.
+    pub fn from_tiles<Data: PartialEq>(tiles: &[Tile<Data>], compatibility: Compatibility) -> Self {
+        let mut rules = Self::new(tiles.len());
+
+        for tile in tiles.iter() {
+            for neighbor in tiles.iter() {
+                for location in Location::iter() {
+                    if tile.is_compatible(neighbor, location, compatibility) {
+                        rules.allowed[tile][location].push(neighbor.id);
+                    }
+                }
+            }
+        }
+
+        rules
+    }
+
+    /// Allows `neighbor` to be placed at `location` of `tile`.
+    pub fn allow(&mut self, tile: TileId, location: Location, neighbor: TileId) {
+        Self::insert(&mut self.allowed[&tile][location], neighbor);
+        Self::insert(&mut self.allowed[&neighbor][location.opposite()], tile);
+    }
+
+    /// The tiles which may be placed at `location` of `tile`.
+    pub fn allowed(&self, tile: TileId, location: Location) -> &[TileId] {
+        &self.allowed[&tile][location]
+    }
+
+    pub fn num_tiles(&self) -> usize {
+        self.allowed.len()
+    }
+
+    /// Adds a tile, which is not yet allowed next to anything, to the end of
+    /// the rules.
+    pub(crate) fn add_tile(&mut self) {
+        let mut allowed = std::mem::take(&mut self.allowed.0).into_vec();
+        allowed.push(LocationTable::default());
+        self.allowed = TileTable(allowed.into_boxed_slice());
+    }
+
+    fn insert(allowed: &mut Vec<TileId>, tile: TileId) {
+        if !allowed.contains(&tile) {
+            allowed.push(tile);
+        }
+    }
+}
@@ -0,0 +1,629 @@
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use bincode::Options;
+use enum_map::Enum;
+use serde::de::{DeserializeOwned, Error as _};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use strum::IntoEnumIterator;
+
+use crate::backtracking::Backtracking;
+use crate::cells::Cell;
+use crate::events::Events;
+use crate::model::Model;
+use crate::patterns::Compatibility;
+use crate::rules::AdjacencyRules;
+use crate::selectors::{CellSelector, Entropy, SavedSelector};
+use crate::slots::Location;
+use crate::tiles::Tile;
+use crate::{Periodicity, Wave, WaveCollapseError};
+
+/// Written at the start of every saved wave.
+const WAVE_MAGIC: [u8; 4] = *b"WFCW";
+
+/// Written at the start of every model saved with [`Model::save_binary`].
+const MODEL_MAGIC: [u8; 4] = *b"WFCM";
+
+/// The version of the format of saved waves and models, bumped whenever it
+/// changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The most bytes read for a saved wave or binary model, so that a corrupted
+/// length can not allocate without bound.
+pub const SIZE_LIMIT: u64 = 1 << 30;
+
+/// The options of [`bincode::deserialize_from`] with [`SIZE_LIMIT`].
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(SIZE_LIMIT)
+}
+
+fn write_header<W: Write>(mut writer: W, magic: [u8; 4]) -> bincode::Result<()> {
+    writer.write_all(&magic)?;
+    bincode::serialize_into(writer, &FORMAT_VERSION)
+}
+
+fn read_header<Rd: Read>(mut reader: Rd, magic: [u8; 4], what: &str) -> bincode::Result<()> {
+    let mut read = [0; 4];
+    reader.read_exact(&mut read)?;
+    if read != magic {
+        return Err(bincode::Error::custom(format!("not a saved {}", what)));
+    }
+
+    let version: u32 = bincode_options().deserialize_from(reader)?;
+    check_version(version, what)
+}
+
+fn check_version<E: serde::de::Error>(version: u32, what: &str) -> Result<(), E> {
+    if version != FORMAT_VERSION {
+        return Err(E::custom(format!(
+            "saved {}s of version {} are not supported, only version {}",
+            what, version, FORMAT_VERSION
+        )));
+    }
+
+    Ok(())
+}
+
+/// A model is stored as its tiles and rules, everything derived from them is
+/// recomputed when it is loaded.
+#[derive(Serialize)]
+struct ModelRef<'m, Data> {
+    tiles: &'m [Tile<Data>],
+    rules: &'m AdjacencyRules,
+    compatibility: Compatibility,
+}
+
+#[derive(Deserialize)]
+struct SavedModel<Data> {
+    tiles: Box<[Tile<Data>]>,
+    rules: AdjacencyRules,
+    compatibility: Compatibility,
+}
+
+impl<Data> SavedModel<Data> {
+    /// Checks what [`Model::build`] and collapsing waves rely on.
+    fn validate(&self) -> Result<(), String> {
+        let num_tiles = self.tiles.len();
+
+        if self.rules.num_tiles() != num_tiles {
+            return Err(format!(
+                "{} tiles but rules for {}",
+                num_tiles,
+                self.rules.num_tiles()
+            ));
+        }
+
+        let size = self.tiles.first().map_or(1, |t| t.pattern().size());
+        for (index, tile) in self.tiles.iter().enumerate() {
+            if *tile.id() != index {
+                return Err(format!("tile {} has the id {}", index, *tile.id()));
+            }
+            if tile.pattern().size() != size || tile.data().len() != size * size {
+                return Err(format!(
+                    "the pattern of tile {} is not {}x{}",
+                    index, size, size
+                ));
+            }
+            for location in Location::iter() {
+                if self
+                    .rules
+                    .allowed(tile.id(), location)
+                    .iter()
+                    .any(|n| **n >= num_tiles)
+                {
+                    return Err(format!("tile {} allows a tile which does not exist", index));
+                }
+            }
+        }
+
+        // the support of a tile is counted from the rules of its neighbors,
+        // so every rule needs its opposite and may only be listed once
+        let mut rules: Vec<_> = self
+            .tiles
+            .iter()
+            .flat_map(|tile| {
+                Location::iter().flat_map(move |location| {
+                    self.rules
+                        .allowed(tile.id(), location)
+                        .iter()
+                        .map(move |neighbor| (*tile.id(), location.into_usize(), **neighbor))
+                })
+            })
+            .collect();
+        rules.sort_unstable();
+
+        if let Some(window) = rules.windows(2).find(|w| w[0] == w[1]) {
+            return Err(format!(
+                "tile {} allows tile {} twice",
+                window[0].0, window[0].2
+            ));
+        }
+        for (tile, location, neighbor) in rules.iter() {
+            let opposite = Location::from_usize(*location).opposite().into_usize();
+            if rules.binary_search(&(*neighbor, opposite, *tile)).is_err() {
+                return Err(format!(
+                    "tile {} allows tile {} but not the other way around",
+                    tile, neighbor
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<Data: Serialize> Serialize for Model<Data> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ModelRef {
+            tiles: self.tiles(),
+            rules: self.rules(),
+            compatibility: self.compatibility(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, Data: Deserialize<'de>> Deserialize<'de> for Model<Data> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let saved = SavedModel::deserialize(deserializer)?;
+        saved.validate().map_err(D::Error::custom)?;
+
+        Model::build(saved.tiles, saved.rules, saved.compatibility).map_err(D::Error::custom)
+    }
+}
+
+/// Everything needed to continue collapsing a wave other than its listener.
+#[derive(Serialize)]
+struct WaveRef<'w, Data, R> {
+    model: &'w Model<Data>,
+    cells: &'w [Cell],
+    backtracking: &'w Option<Backtracking>,
+    selector: Option<SavedSelector>,
+    x_cells: usize,
+    y_cells: usize,
+    periodicity: Periodicity,
+    unsupported_removed: bool,
+    failed: Option<WaveCollapseError>,
+    num_collapsed: usize,
+    rng: &'w R,
+}
+
+#[derive(Deserialize)]
+struct SavedWave<Data, R> {
+    model: Model<Data>,
+    cells: Box<[Cell]>,
+    backtracking: Option<Backtracking>,
+    selector: Option<SavedSelector>,
+    x_cells: usize,
+    y_cells: usize,
+    periodicity: Periodicity,
+    unsupported_removed: bool,
+    failed: Option<WaveCollapseError>,
+    num_collapsed: usize,
+    rng: R,
+}
+
+impl<Data: Serialize, R: Serialize> Serialize for Wave<Data, R> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // removals are always propagated before a change of the wave returns
+        debug_assert!(self.pending.is_empty());
+
+        WaveRef {
+            model: &self.model,
+            cells: &self.cells,
+            backtracking: &self.backtracking,
+            selector: self.selector.save(),
+            x_cells: self.x_cells,
+            y_cells: self.y_cells,
+            periodicity: self.periodicity,
+            unsupported_removed: self.unsupported_removed,
+            failed: self.failed,
+            num_collapsed: self.num_collapsed,
+            rng: &self.rng,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, Data: Deserialize<'de>, R: Deserialize<'de>> Deserialize<'de> for Wave<Data, R> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let saved = SavedWave::<Data, R>::deserialize(deserializer)?;
+
+        if saved.x_cells == 0
+            || saved.y_cells == 0
+            || saved.cells.len() != saved.x_cells * saved.y_cells
+        {
+            return Err(D::Error::custom(format!(
+                "{} cells for a wave of {}x{}",
+                saved.cells.len(),
+                saved.x_cells,
+                saved.y_cells
+            )));
+        }
+
+        let num_tiles = saved.model.tiles().len();
+        for (index, cell) in saved.cells.iter().enumerate() {
+            cell.validate(&saved.model)
+                .map_err(|e| D::Error::custom(format!("cell {}: {}", index, e)))?;
+        }
+
+        // the counts of a failed wave are left as they were when it failed
+        let num_collapsed = saved.cells.iter().filter(|c| c.collapsed()).count();
+        if saved.failed.is_none()
+            && (saved.num_collapsed != num_collapsed || saved.cells.iter().any(Cell::invalid))
+        {
+            return Err(D::Error::custom(format!(
+                "{} of the cells are collapsed but {} are counted",
+                num_collapsed, saved.num_collapsed
+            )));
+        }
+
+        if let Some(backtracking) = &saved.backtracking {
+            backtracking
+                .validate(saved.cells.len(), num_tiles)
+                .map_err(D::Error::custom)?;
+        }
+
+        let selector: Box<dyn CellSelector> = match saved.selector {
+            Some(selector) => selector.restore(&saved.cells).map_err(D::Error::custom)?,
+            None => Box::new(Entropy::default()),
+        };
+
+        let wave = Wave {
+            model: Arc::new(saved.model),
+            cells: saved.cells,
+            backtracking: saved.backtracking,
+            pending: Default::default(),
+            events: Events::default(),
+            selector,
+            x_cells: saved.x_cells,
+            y_cells: saved.y_cells,
+            periodicity: saved.periodicity,
+            unsupported_removed: saved.unsupported_removed,
+            failed: saved.failed,
+            num_collapsed: saved.num_collapsed,
+            rng: saved.rng,
+        };
+
+        // the support of a failed wave is left as it was when it failed,
+        // it never changes again
+        if wave.failed.is_none() {
+            wave.validate_support().map_err(D::Error::custom)?;
+        }
+
+        Ok(wave)
+    }
+}
+
+impl<Data, R> Wave<Data, R> {
+    /// Checks that every cell counts the tiles of its neighbors which allow
+    /// each of its tiles, which holds once every removal was propagated.
+    fn validate_support(&self) -> Result<(), String> {
+        let num_tiles = self.model.tiles().len();
+
+        for (index, cell) in self.cells.iter().enumerate() {
+            for (location, neighbor) in self.get_neighbors(index) {
+                let neighbor = neighbor
+                    .map(|n| &self.cells[n])
+                    .filter(|n| n.num_remaining_tiles() < num_tiles);
+
+                for tile in self.model.tiles() {
+                    let allowed = self.model.allowed(tile.id, location);
+                    // cells without a neighbor keep the counts of the model
+                    let expected = match neighbor {
+                        Some(neighbor) => allowed
+                            .iter()
+                            .filter(|n| neighbor.is_remaining(**n))
+                            .count(),
+                        None => allowed.len(),
+                    };
+
+                    if cell.ways_to_become_tile(&self.model, tile.id, location) as usize != expected
+                    {
+                        return Err(format!(
+                            "the support of tile {} in cell {} does not match its neighbors",
+                            *tile.id, index
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<Data: Serialize, R: Serialize> Wave<Data, R> {
+    /// Writes the state of the wave along with its model, so that
+    /// [`Wave::resume`] continues collapsing it exactly as this wave would,
+    /// such as to pause a long generation or to reproduce a contradiction.
+    ///
+    /// The listener is not saved, and neither is a custom selector which
+    /// [`CellSelector::save`] does not support.
+    pub fn save<W: Write>(&self, mut writer: W) -> bincode::Result<()> {
+        write_header(&mut writer, WAVE_MAGIC)?;
+        bincode::serialize_into(writer, self)
+    }
+}
+
+impl<Data: DeserializeOwned, R: DeserializeOwned> Wave<Data, R> {
+    /// Reads a wave written by [`Wave::save`] with the current
+    /// [`FORMAT_VERSION`], the resumed wave has its own copy of the model.
+    pub fn resume<Rd: Read>(mut reader: Rd) -> bincode::Result<Self> {
+        read_header(&mut reader, WAVE_MAGIC, "wave")?;
+        bincode_options().deserialize_from(reader)
+    }
+}
+
+/// A model file in JSON, tagged with the format version.
+#[derive(Serialize)]
+struct JsonModelRef<'m, Data> {
+    version: u32,
+    model: &'m Model<Data>,
+}
+
+impl<Data: Serialize> Model<Data> {
+    /// Writes the tiles of the model with their weights and which tiles may
+    /// be adjacent as indented JSON, to inspect or edit by hand.
+    pub fn save_json<W: Write>(&self, writer: W) -> serde_json::Result<()> {
+        let model = JsonModelRef {
+            version: FORMAT_VERSION,
+            model: self,
+        };
+
+        serde_json::to_writer_pretty(writer, &model)
+    }
+
+    /// Writes the model in a compact binary format which is faster to load
+    /// than JSON, such as to ship a model with a game.
+    pub fn save_binary<W: Write>(&self, mut writer: W) -> bincode::Result<()> {
+        write_header(&mut writer, MODEL_MAGIC)?;
+        bincode::serialize_into(writer, self)
+    }
+}
+
+impl<Data: DeserializeOwned> Model<Data> {
+    /// Reads a model written by [`Model::save_json`] with the current
+    /// [`FORMAT_VERSION`], checking that its tiles and rules are consistent.
+    pub fn load_json<Rd: Read>(reader: Rd) -> serde_json::Result<Self> {
+        let mut json: serde_json::Value = serde_json::from_reader(reader)?;
+
+        let version = json["version"]
+            .as_u64()
+            .ok_or_else(|| serde_json::Error::custom("a saved model needs a version"))?;
+        check_version(version.try_into().unwrap_or(u32::MAX), "model")?;
+
+        serde_json::from_value(json["model"].take())
+    }
+
+    /// Reads a model written by [`Model::save_binary`] like
+    /// [`Model::load_json`].
+    pub fn load_binary<Rd: Read>(mut reader: Rd) -> bincode::Result<Self> {
+        read_header(&mut reader, MODEL_MAGIC, "model")?;
+        bincode_options().deserialize_from(reader)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use strum::IntoEnumIterator;
+
+    use super::*;
+    use crate::events::Step;
+    use crate::patterns::{self, Symmetry};
+    use crate::selectors::Spiral;
+    use crate::test_util::coloring_with;
+    use crate::WaveCollapseError;
+
+    /// colors of different weights, so that resumed waves depend on them
+    fn coloring(num_colors: usize) -> Arc<Model<usize>> {
+        coloring_with(num_colors, |c| (c, 1.0 + c as f64))
+    }
+
+    fn save_and_resume(wave: &Wave<usize>) -> Wave<usize> {
+        let mut saved = Vec::new();
+        wave.save(&mut saved).unwrap();
+
+        Wave::resume(&saved[..]).unwrap()
+    }
+
+    fn rest(wave: &mut Wave<usize>) -> Vec<Result<Step, String>> {
+        wave.steps()
+            .map(|step| step.map_err(|e| format!("{:?}", e)))
+            .collect()
+    }
+
+    #[test]
+    fn resumes_where_it_left_off() {
+        let model = coloring(3);
+
+        for seed in 0..10 {
+            let mut wave = model.wave(9, 7).with_seed(seed).with_backtracking(20);
+            for _ in 0..15 {
+                wave.collapse().unwrap();
+            }
+
+            let mut resumed = save_and_resume(&wave);
+            assert_eq!(rest(&mut resumed), rest(&mut wave));
+            assert_eq!(resumed.to_grid(), wave.to_grid());
+        }
+    }
+
+    #[test]
+    fn resumes_before_the_first_step() {
+        let model = coloring(4);
+        let mut wave = model
+            .wave(6, 6)
+            .with_seed(3)
+            .with_selector(Spiral::new(1, 1));
+        wave.ban(0, 0usize.into()).unwrap();
+
+        let mut resumed = save_and_resume(&wave);
+        assert_eq!(rest(&mut resumed), rest(&mut wave));
+    }
+
+    #[test]
+    fn resumes_contradictions() {
+        let model = coloring(2);
+        let mut wave = model.wave(3, 3).with_seed(1);
+
+        let mut resumed = save_and_resume(&wave);
+        let error = resumed.run().unwrap_err();
+        assert!(matches!(error, WaveCollapseError::InvalidCell(_)));
+        assert_eq!(
+            format!("{:?}", error),
+            format!("{:?}", wave.run().unwrap_err())
+        );
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let model = coloring(2);
+        let mut saved = Vec::new();
+        model.wave(2, 2).save(&mut saved).unwrap();
+
+        saved[4] += 1;
+        let error = Wave::<usize>::resume(&saved[..]).unwrap_err();
+        assert!(error.to_string().contains("version"));
+
+        assert!(Wave::<usize>::resume(&b"PNG..."[..]).is_err());
+    }
+
+    #[test]
+    fn rejects_inconsistent_waves() {
+        let model = coloring(3);
+        let mut wave = model.wave(4, 4).with_seed(2).with_backtracking(10);
+        for _ in 0..3 {
+            wave.collapse().unwrap();
+        }
+
+        let mut saved = Vec::new();
+        wave.save(&mut saved).unwrap();
+        assert!(Wave::<usize>::resume(&saved[..saved.len() - 1]).is_err());
+
+        let value = serde_json::to_value(&wave).unwrap();
+        let edits = [
+            (
+                "/cells/0/num_remaining_tiles",
+                serde_json::json!(2),
+                "remaining tiles",
+            ),
+            (
+                "/cells/1/remaining_tiles/words",
+                serde_json::json!([1, 1]),
+                "remaining tiles",
+            ),
+            ("/num_collapsed", serde_json::json!(0), "collapsed"),
+            ("/cells/2/sum_weights", serde_json::json!(1.0), "weights"),
+            (
+                "/backtracking/decisions/0/cell_index",
+                serde_json::json!(16),
+                "backtracking",
+            ),
+        ];
+
+        for (pointer, replacement, expected) in edits {
+            let mut edited = value.clone();
+            *edited.pointer_mut(pointer).unwrap() = replacement;
+
+            let error = serde_json::from_value::<Wave<usize>>(edited).unwrap_err();
+            assert!(error.to_string().contains(expected), "{}", error);
+        }
+
+        // counts which would underflow once a neighbor loses a tile
+        let mut edited = value.clone();
+        let zero =
+            serde_json::json!({ "location_map": { "North": 0, "East": 0, "South": 0, "West": 0 } });
+        for cell in edited["cells"].as_array_mut().unwrap() {
+            cell["ways_to_become_tile"] = serde_json::json!([zero, zero, zero]);
+        }
+
+        let error = serde_json::from_value::<Wave<usize>>(edited).unwrap_err();
+        assert!(error.to_string().contains("support"), "{}", error);
+    }
+
+    #[test]
+    fn models_round_trip() {
+        let input = vec![vec![0, 1, 2, 2], vec![2, 0, 1, 1], vec![1, 1, 0, 2]];
+        let tiles = patterns::extract(&input, 2, Symmetry::ALL, Periodicity::Both);
+        let model = Arc::new(Model::new(tiles, Compatibility::Overlapping).unwrap());
+
+        let mut json = Vec::new();
+        model.save_json(&mut json).unwrap();
+        let mut binary = Vec::new();
+        model.save_binary(&mut binary).unwrap();
+        assert!(binary.len() < json.len());
+
+        let loaded = [
+            Model::<usize>::load_json(&json[..]).unwrap(),
+            Model::<usize>::load_binary(&binary[..]).unwrap(),
+        ];
+
+        for loaded in loaded.map(Arc::new) {
+            assert_eq!(loaded.compatibility(), model.compatibility());
+            assert_eq!(loaded.tiles().len(), model.tiles().len());
+
+            for (a, b) in loaded.tiles().iter().zip(model.tiles()) {
+                assert_eq!(
+                    (a.id(), a.data(), a.probability()),
+                    (b.id(), b.data(), b.probability())
+                );
+                for location in Location::iter() {
+                    assert_eq!(
+                        loaded.allowed(a.id(), location),
+                        model.allowed(b.id(), location)
+                    );
+                }
+            }
+
+            let mut wave = loaded.wave(7, 5).with_seed(2).with_backtracking(50);
+            assert_eq!(
+                rest(&mut wave),
+                rest(&mut model.wave(7, 5).with_seed(2).with_backtracking(50))
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_inconsistent_models() {
+        let mut json = Vec::new();
+        coloring(2).save_json(&mut json).unwrap();
+        let mut value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+
+        value["model"]["tiles"][1]["id"] = 5.into();
+        let error = Model::<usize>::load_json(value.to_string().as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("tile 1 has the id 5"));
+
+        value["version"] = 2.into();
+        let error = Model::<usize>::load_json(value.to_string().as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("version"));
+
+        assert!(Model::<usize>::load_binary(&json[..]).is_err());
+    }
+
+    #[test]
+    fn rejects_asymmetric_rules() {
+        let mut json = Vec::new();
+        coloring(2).save_json(&mut json).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+
+        let mut asymmetric = value.clone();
+        asymmetric["model"]["rules"]["allowed"][0]["North"] = serde_json::json!([]);
+        let error = Model::<usize>::load_json(asymmetric.to_string().as_bytes()).unwrap_err();
+        assert!(
+            error.to_string().contains("not the other way around"),
+            "{}",
+            error
+        );
+
+        let mut duplicated = value;
+        duplicated["model"]["rules"]["allowed"][0]["North"] = serde_json::json!([1, 1]);
+        let error = Model::<usize>::load_json(duplicated.to_string().as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("twice"), "{}", error);
+    }
+}
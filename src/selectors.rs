@@ -0,0 +1,486 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fmt::Debug;
+
+use rand::{Rng, RngCore};
+
+use crate::cells::Cell;
+use crate::retry::derive_seed;
+
+/// Decides which cell the wave observes next.
+pub trait CellSelector: Debug + Send {
+    /// Picks one of the uncollapsed cells, there is always at least one.
+    ///
+    /// The cells are laid out in rows of `x_cells`.
+    fn select(&mut self, cells: &[Cell], x_cells: usize, rng: &mut dyn RngCore) -> usize;
+
+    /// Called whenever the remaining tiles of a cell change, including when
+    /// tiles are restored by backtracking.
+    fn cell_changed(&mut self, _index: usize, _cell: &Cell) {}
+
+    /// The state to restore the selector from when a saved wave is resumed,
+    /// see [`crate::Wave::save`]. Waves whose selector returns `None` resume
+    /// with the default [`Entropy`] selector.
+    #[cfg(feature = "serde")]
+    fn save(&self) -> Option<SavedSelector> {
+        None
+    }
+}
+
+/// The state of one of the selectors of this module, see
+/// [`CellSelector::save`].
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum SavedSelector {
+    Entropy {
+        noise_seed: u64,
+        /// `None` if no cell was selected yet
+        generations: Option<Vec<u32>>,
+    },
+    MinimumRemainingValues,
+    Random,
+    Scanline,
+    Spiral {
+        start: Option<(usize, usize)>,
+    },
+}
+
+#[cfg(feature = "serde")]
+impl SavedSelector {
+    /// Recreates the selector for the cells of the resumed wave.
+    pub(crate) fn restore(self, cells: &[Cell]) -> Result<Box<dyn CellSelector>, String> {
+        Ok(match self {
+            SavedSelector::Entropy {
+                noise_seed,
+                generations,
+            } => {
+                let mut entropy = Entropy::default();
+
+                // the queue only ever holds one current entry per uncollapsed
+                // cell, so it is rebuilt from the cells
+                if let Some(generations) = generations {
+                    if generations.len() != cells.len() {
+                        return Err(format!(
+                            "{} generations for {} cells",
+                            generations.len(),
+                            cells.len()
+                        ));
+                    }
+
+                    entropy.initialized = true;
+                    entropy.noise_seed = noise_seed;
+                    entropy.generations = generations;
+
+                    for (index, cell) in cells.iter().enumerate().filter(|(_, c)| c.uncollapsed()) {
+                        entropy.push(index, cell);
+                    }
+                }
+
+                Box::new(entropy)
+            }
+            SavedSelector::MinimumRemainingValues => Box::new(MinimumRemainingValues),
+            SavedSelector::Random => Box::new(Random),
+            SavedSelector::Scanline => Box::new(Scanline::default()),
+            SavedSelector::Spiral { start } => Box::new(Spiral {
+                start,
+                ordered: None,
+            }),
+        })
+    }
+}
+
+/// Picks randomly between the cells with the fewest remaining tiles according
+/// to `key`.
+fn lowest_by<K: PartialOrd>(
+    cells: &[Cell],
+    rng: &mut dyn RngCore,
+    key: impl Fn(&Cell) -> K,
+) -> usize {
+    let mut uncolapsed_cells: Box<_> = cells
+        .iter()
+        .enumerate()
+        .filter_map(|(i, c)| {
+            if c.uncollapsed() {
+                Some((i, key(c)))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    uncolapsed_cells.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+    assert!(!uncolapsed_cells.is_empty());
+    let min = &uncolapsed_cells[0].1;
+
+    let lowest: Box<_> = uncolapsed_cells
+        .iter()
+        .filter_map(|(i, c)| if c == min { Some(*i) } else { None })
+        .collect();
+
+    lowest[rng.gen_range(0..lowest.len())]
+}
+
+/// Observes the cell with the lowest Shannon entropy, the most constrained
+/// cell taking the tile probabilities into account.
+///
+/// The cells are kept in a priority queue, entries are not removed when a cell
+/// changes but skipped once they are popped if a newer entry for the cell
+/// exists. Ties are broken by a small amount of noise added to the entropy.
+#[derive(Debug, Default, Clone)]
+pub struct Entropy {
+    heap: BinaryHeap<EntropyEntry>,
+    /// bumped whenever a cell changes, entries of older generations are stale
+    generations: Vec<u32>,
+    noise_seed: u64,
+    initialized: bool,
+}
+
+#[derive(Debug, Clone)]
+struct EntropyEntry {
+    entropy: f64,
+    index: usize,
+    generation: u32,
+}
+
+impl PartialEq for EntropyEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for EntropyEntry {}
+
+impl PartialOrd for EntropyEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for EntropyEntry {
+    /// the lowest entropy is the greatest so it is at the top of the heap
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .entropy
+            .total_cmp(&self.entropy)
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+impl Entropy {
+    /// far smaller than the difference between the entropy of any two
+    /// different sets of tiles
+    const NOISE: f64 = 1e-6;
+
+    fn push(&mut self, index: usize, cell: &Cell) {
+        let generation = self.generations[index];
+        let noise = derive_seed(self.noise_seed ^ ((generation as u64) << 32), index);
+        let noise = (noise >> 11) as f64 / (1u64 << 53) as f64;
+
+        self.heap.push(EntropyEntry {
+            entropy: cell.entropy() + noise * Self::NOISE,
+            index,
+            generation,
+        });
+    }
+}
+
+impl CellSelector for Entropy {
+    fn select(&mut self, cells: &[Cell], _x_cells: usize, rng: &mut dyn RngCore) -> usize {
+        if !self.initialized {
+            self.initialized = true;
+            self.noise_seed = rng.gen();
+            self.generations = vec![0; cells.len()];
+
+            for (index, cell) in cells.iter().enumerate().filter(|(_, c)| c.uncollapsed()) {
+                self.push(index, cell);
+            }
+        }
+
+        loop {
+            let entry = self
+                .heap
+                .pop()
+                .expect("every uncollapsed cell has an entry");
+
+            if entry.generation == self.generations[entry.index] && cells[entry.index].uncollapsed()
+            {
+                return entry.index;
+            }
+        }
+    }
+
+    fn cell_changed(&mut self, index: usize, cell: &Cell) {
+        if !self.initialized {
+            return;
+        }
+
+        self.generations[index] = self.generations[index].wrapping_add(1);
+
+        if cell.uncollapsed() {
+            self.push(index, cell);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    fn save(&self) -> Option<SavedSelector> {
+        Some(SavedSelector::Entropy {
+            noise_seed: self.noise_seed,
+            generations: self.initialized.then(|| self.generations.clone()),
+        })
+    }
+}
+
+/// Observes the cell with the fewest remaining tiles.
+#[derive(Debug, Default, Clone)]
+pub struct MinimumRemainingValues;
+
+impl CellSelector for MinimumRemainingValues {
+    fn select(&mut self, cells: &[Cell], _x_cells: usize, rng: &mut dyn RngCore) -> usize {
+        lowest_by(cells, rng, |c| c.num_remaining_tiles())
+    }
+
+    #[cfg(feature = "serde")]
+    fn save(&self) -> Option<SavedSelector> {
+        Some(SavedSelector::MinimumRemainingValues)
+    }
+}
+
+/// Observes a random uncollapsed cell.
+#[derive(Debug, Default, Clone)]
+pub struct Random;
+
+impl CellSelector for Random {
+    fn select(&mut self, cells: &[Cell], _x_cells: usize, rng: &mut dyn RngCore) -> usize {
+        let num_uncollapsed = cells.iter().filter(|c| c.uncollapsed()).count();
+        let nth = rng.gen_range(0..num_uncollapsed);
+
+        cells
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.uncollapsed())
+            .nth(nth)
+            .unwrap()
+            .0
+    }
+
+    #[cfg(feature = "serde")]
+    fn save(&self) -> Option<SavedSelector> {
+        Some(SavedSelector::Random)
+    }
+}
+
+/// Observes the cells in a fixed order, skipping collapsed cells.
+#[derive(Debug, Default, Clone)]
+struct Ordered {
+    order: Vec<usize>,
+    /// the position of each cell in the order
+    positions: Vec<usize>,
+    /// every cell before the cursor in the order is collapsed
+    cursor: usize,
+}
+
+impl Ordered {
+    fn new(order: Vec<usize>) -> Self {
+        let mut positions = vec![0; order.len()];
+        for (position, index) in order.iter().enumerate() {
+            positions[*index] = position;
+        }
+
+        Self {
+            order,
+            positions,
+            cursor: 0,
+        }
+    }
+
+    fn select(&mut self, cells: &[Cell]) -> usize {
+        while !cells[self.order[self.cursor]].uncollapsed() {
+            self.cursor += 1;
+        }
+
+        self.order[self.cursor]
+    }
+
+    fn cell_changed(&mut self, index: usize, cell: &Cell) {
+        if cell.uncollapsed() {
+            self.cursor = self.cursor.min(self.positions[index]);
+        }
+    }
+}
+
+/// Observes the cells row by row starting from the top left.
+#[derive(Debug, Default, Clone)]
+pub struct Scanline {
+    ordered: Option<Ordered>,
+}
+
+impl CellSelector for Scanline {
+    fn select(&mut self, cells: &[Cell], _x_cells: usize, _rng: &mut dyn RngCore) -> usize {
+        self.ordered
+            .get_or_insert_with(|| Ordered::new((0..cells.len()).collect()))
+            .select(cells)
+    }
+
+    fn cell_changed(&mut self, index: usize, cell: &Cell) {
+        if let Some(ordered) = &mut self.ordered {
+            ordered.cell_changed(index, cell);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    fn save(&self) -> Option<SavedSelector> {
+        Some(SavedSelector::Scanline)
+    }
+}
+
+/// Observes the cells in a square spiral growing outwards from a starting
+/// cell, by default the center of the wave.
+#[derive(Debug, Default, Clone)]
+pub struct Spiral {
+    start: Option<(usize, usize)>,
+    ordered: Option<Ordered>,
+}
+
+impl Spiral {
+    /// Starts the spiral at column `x` and row `y`, or at the center if the
+    /// cell is outside of the wave.
+    pub fn new(x: usize, y: usize) -> Self {
+        Self {
+            start: Some((x, y)),
+            ordered: None,
+        }
+    }
+
+    fn order(&self, x_cells: usize, y_cells: usize) -> Vec<usize> {
+        let (x, y) = self
+            .start
+            .filter(|&(x, y)| x < x_cells && y < y_cells)
+            .unwrap_or((x_cells / 2, y_cells / 2));
+
+        let num_cells = x_cells * y_cells;
+        let mut order = Vec::with_capacity(num_cells);
+        let (mut x, mut y) = (x as isize, y as isize);
+        // east, south, west, north
+        let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+        order.push(y as usize * x_cells + x as usize);
+
+        // the legs of the spiral grow by one every second turn
+        let mut leg = 0;
+        while order.len() < num_cells {
+            let (dx, dy) = directions[leg % 4];
+            for _ in 0..(leg / 2 + 1) {
+                x += dx;
+                y += dy;
+
+                if (0..x_cells as isize).contains(&x) && (0..y_cells as isize).contains(&y) {
+                    order.push(y as usize * x_cells + x as usize);
+                }
+            }
+            leg += 1;
+        }
+
+        order
+    }
+}
+
+impl CellSelector for Spiral {
+    fn select(&mut self, cells: &[Cell], x_cells: usize, _rng: &mut dyn RngCore) -> usize {
+        if self.ordered.is_none() {
+            self.ordered = Some(Ordered::new(self.order(x_cells, cells.len() / x_cells)));
+        }
+
+        self.ordered.as_mut().unwrap().select(cells)
+    }
+
+    fn cell_changed(&mut self, index: usize, cell: &Cell) {
+        if let Some(ordered) = &mut self.ordered {
+            ordered.cell_changed(index, cell);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    fn save(&self) -> Option<SavedSelector> {
+        Some(SavedSelector::Spiral { start: self.start })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{assert_adjacencies, chain};
+
+    fn run<S: CellSelector + 'static>(selector: S) {
+        let model = chain();
+        let mut wave = model.wave(7, 5).with_selector(selector).with_seed(3);
+        wave.run().unwrap();
+
+        assert_adjacencies(&wave, &model);
+    }
+
+    #[test]
+    fn every_selector_collapses() {
+        run(Entropy::default());
+        run(MinimumRemainingValues);
+        run(Random);
+        run(Scanline::default());
+        run(Spiral::default());
+        run(Spiral::new(6, 4));
+    }
+
+    #[test]
+    fn entropy_selects_lowest() {
+        let model = chain();
+        let mut wave = model.wave(9, 6).with_seed(5);
+
+        while !wave.collapsed() {
+            let entropies: Vec<_> = wave
+                .cells
+                .iter()
+                .map(|c| c.uncollapsed().then(|| c.entropy()))
+                .collect();
+            let lowest = entropies
+                .iter()
+                .flatten()
+                .copied()
+                .fold(f64::INFINITY, f64::min);
+
+            let step = wave.collapse().unwrap().unwrap();
+            assert!(entropies[step.cell_index].unwrap() - lowest <= Entropy::NOISE);
+        }
+    }
+
+    #[test]
+    fn ordered_selectors_start_in_place() {
+        let model = chain();
+
+        let mut wave = model.wave(7, 5).with_selector(Scanline::default());
+        wave.collapse().unwrap();
+        assert!(wave.choosen_tile(0).is_some());
+
+        for spiral in [Spiral::default(), Spiral::new(7, 0)] {
+            let mut wave = model.wave(7, 5).with_selector(spiral);
+            wave.collapse().unwrap();
+            assert!(wave.choosen_tile(2 * 7 + 3).is_some());
+        }
+    }
+
+    #[test]
+    fn spiral_order() {
+        #[rustfmt::skip]
+        let expected = vec![
+            4, 5, 8, 7, 6, 3, 0, 1, 2,
+        ];
+
+        assert_eq!(Spiral::default().order(3, 3), expected);
+
+        #[rustfmt::skip]
+        let expected = vec![
+            0, 1, 5, 4,
+            2, 6, 3, 7,
+        ];
+
+        assert_eq!(Spiral::new(0, 0).order(4, 2), expected);
+    }
+}
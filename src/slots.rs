@@ -4,6 +4,7 @@ use enum_map::{Enum, EnumMap};
 use strum::EnumIter;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, Enum)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Location {
     North,
     East,
@@ -41,6 +42,7 @@ impl Location {
 }
 
 #[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct LocationTable<T>(EnumMap<Location, T>);
 
 impl<T> Deref for LocationTable<T> {
@@ -90,7 +92,7 @@ where
             .all(|(e1, e2)| e1 == e2)
     }
 
-    pub fn data(&self) -> &Box<[&'a Data]> {
+    pub fn data(&self) -> &[&'a Data] {
         &self.data
     }
 }
@@ -103,6 +105,7 @@ mod test {
         use super::*;
 
         #[test]
+        #[allow(clippy::bool_assert_comparison, clippy::useless_vec)]
         fn can_be_adjacent() {
             assert_eq!(
                 true,
@@ -112,25 +115,25 @@ mod test {
             assert_eq!(
                 true,
                 Slot::new(vec![2, 2, 3, 1].iter().collect(), Location::East).can_be_adjacent(
-                    &Slot::new(vec![1, 3, 2, 2].iter().collect(), Location::West)
+                    &Slot::new(vec![2, 2, 3, 1].iter().collect(), Location::West)
                 )
             );
             assert_eq!(
                 true,
                 Slot::new(vec![2, 2, 3, 1].iter().collect(), Location::South).can_be_adjacent(
-                    &Slot::new(vec![1, 3, 2, 2].iter().collect(), Location::North)
+                    &Slot::new(vec![2, 2, 3, 1].iter().collect(), Location::North)
                 )
             );
             assert_eq!(
                 true,
                 Slot::new(vec![2, 2, 3, 1].iter().collect(), Location::East).can_be_adjacent(
-                    &Slot::new(vec![1, 3, 2, 2].iter().collect(), Location::West)
+                    &Slot::new(vec![2, 2, 3, 1].iter().collect(), Location::West)
                 )
             );
             assert_eq!(
                 true,
                 Slot::new(vec![2, 2, 3, 1].iter().collect(), Location::West).can_be_adjacent(
-                    &Slot::new(vec![1, 3, 2, 2].iter().collect(), Location::East)
+                    &Slot::new(vec![2, 2, 3, 1].iter().collect(), Location::East)
                 )
             );
 
@@ -139,10 +142,18 @@ mod test {
                 Slot::new(vec![1, 2, 1].iter().collect(), Location::North)
                     .can_be_adjacent(&Slot::new(vec![1, 1, 1].iter().collect(), Location::South))
             );
+            // both slots are read in the same direction, left to right or top
+            // to bottom, so a reversed partner does not match
             assert_eq!(
                 false,
                 Slot::new(vec![2, 2, 1].iter().collect(), Location::North)
-                    .can_be_adjacent(&Slot::new(vec![2, 2, 1].iter().collect(), Location::South))
+                    .can_be_adjacent(&Slot::new(vec![1, 2, 2].iter().collect(), Location::South))
+            );
+            assert_eq!(
+                false,
+                Slot::new(vec![2, 2, 3, 1].iter().collect(), Location::East).can_be_adjacent(
+                    &Slot::new(vec![1, 3, 2, 2].iter().collect(), Location::West)
+                )
             );
 
             assert_eq!(
@@ -0,0 +1,70 @@
+use std::sync::Arc;
+
+use strum::IntoEnumIterator;
+
+use crate::model::Model;
+use crate::patterns::Pattern;
+use crate::slots::Location;
+use crate::tiled::TiledModel;
+use crate::Wave;
+
+/// A model where every neighbor must be a different color than the cell.
+///
+/// Two colors can not fill a wave which is an odd number of cells wide or
+/// high, since its rows or columns wrap around into odd cycles, so every
+/// attempt to collapse it fails.
+pub(crate) fn coloring(num_colors: usize) -> Arc<Model<usize>> {
+    coloring_with(num_colors, |color| (color, 1.0))
+}
+
+/// [`coloring`] with the data and weight of each color.
+pub(crate) fn coloring_with<Data>(
+    num_colors: usize,
+    color: impl Fn(usize) -> (Data, f64),
+) -> Arc<Model<Data>> {
+    let mut model = TiledModel::new();
+    let colors: Vec<_> = (0..num_colors)
+        .map(|c| {
+            let (data, weight) = color(c);
+            model.add_tile(Pattern::new(vec![data].into_boxed_slice()), weight)
+        })
+        .collect();
+
+    for a in colors.iter() {
+        for b in colors.iter().filter(|b| *b != a) {
+            for location in Location::iter() {
+                model.allow(*a, location, *b);
+            }
+        }
+    }
+
+    Arc::new(model.build().unwrap())
+}
+
+/// The tiles 'a', 'b' and 'c', where each tile may only be next to itself or
+/// the tiles before and after it, so 'a' and 'c' may not be adjacent.
+pub(crate) fn chain() -> Arc<Model<char>> {
+    let mut model = TiledModel::new();
+    let [a, b, c] = ['a', 'b', 'c']
+        .map(|data| model.add_tile(Pattern::new(vec![data].into_boxed_slice()), 1.0));
+
+    for location in Location::iter() {
+        for (x, y) in [(a, a), (a, b), (b, b), (b, c), (c, c)] {
+            model.allow(x, location, y);
+        }
+    }
+
+    Arc::new(model.build().unwrap())
+}
+
+/// Asserts that every cell of the collapsed wave is next to tiles which the
+/// model allows.
+pub(crate) fn assert_adjacencies<Data>(wave: &Wave<Data>, model: &Model<Data>) {
+    for index in 0..wave.cells.len() {
+        let tile = wave.choosen_tile(index).unwrap().id();
+        for (location, neighbor) in wave.neighbors(index) {
+            let neighbor = wave.choosen_tile(neighbor).unwrap().id();
+            assert!(model.allowed(tile, location).contains(&neighbor));
+        }
+    }
+}
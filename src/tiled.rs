@@ -0,0 +1,95 @@
+use crate::model::{Model, ModelError};
+use crate::patterns::Pattern;
+use crate::rules::AdjacencyRules;
+use crate::slots::Location;
+use crate::tiles::{Tile, TileId};
+
+/// A hand authored tileset where which tiles may be adjacent is declared
+/// rather than inferred from the data of the tiles.
+#[derive(Debug)]
+pub struct TiledModel<Data> {
+    tiles: Vec<Tile<Data>>,
+    rules: AdjacencyRules,
+}
+
+impl<Data> Default for TiledModel<Data> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Data> TiledModel<Data> {
+    pub fn new() -> Self {
+        Self {
+            tiles: Vec::new(),
+            rules: AdjacencyRules::new(0),
+        }
+    }
+
+    /// Registers a tile which will be choosen proportionally to `weight`.
+    ///
+    /// All patterns of a model must be the same size.
+    pub fn add_tile(&mut self, pattern: Pattern<Data>, weight: f64) -> TileId {
+        if let Some(first) = self.tiles.first() {
+            assert_eq!(first.pattern().size(), pattern.size());
+        }
+
+        let id = TileId::from(self.tiles.len());
+        self.tiles.push(Tile::new(pattern, weight, id));
+
+        self.rules.add_tile();
+
+        id
+    }
+
+    /// Allows `neighbor` to be placed at `location` of `tile`, and so `tile` at
+    /// the opposite location of `neighbor`.
+    pub fn allow(&mut self, tile: TileId, location: Location, neighbor: TileId) {
+        self.rules.allow(tile, location, neighbor);
+    }
+
+    pub fn tiles(&self) -> &[Tile<Data>] {
+        &self.tiles
+    }
+
+    pub fn rules(&self) -> &AdjacencyRules {
+        &self.rules
+    }
+
+    /// Precomputes everything needed to create waves of the tiles.
+    pub fn build(self) -> Result<Model<Data>, ModelError> {
+        Model::from_rules(self.tiles.into_boxed_slice(), self.rules)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{assert_adjacencies, chain};
+
+    #[test]
+    fn allow_is_symmetric() {
+        let mut model = TiledModel::new();
+        let a = model.add_tile(Pattern::new(vec!['a'].into_boxed_slice()), 1.0);
+        let b = model.add_tile(Pattern::new(vec!['b'].into_boxed_slice()), 1.0);
+
+        model.allow(a, Location::East, b);
+        model.allow(a, Location::East, b);
+
+        assert_eq!(model.rules().allowed(a, Location::East), &[b]);
+        assert_eq!(model.rules().allowed(b, Location::West), &[a]);
+        assert!(model.rules().allowed(a, Location::West).is_empty());
+        assert!(model.rules().allowed(b, Location::East).is_empty());
+    }
+
+    #[test]
+    fn only_allowed_neighbors() {
+        let model = chain();
+        let mut wave = model.wave(10, 10);
+        while !wave.collapsed() {
+            wave.collapse().unwrap();
+        }
+
+        assert_adjacencies(&wave, &model);
+    }
+}
@@ -1,9 +1,10 @@
 use std::ops::{Deref, DerefMut, Index, IndexMut};
 
-use crate::patterns::Pattern;
+use crate::patterns::{Compatibility, Pattern};
 use crate::slots::Location;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TileId(usize);
 
 impl<T: Into<usize>> From<T> for TileId {
@@ -21,6 +22,7 @@ impl Deref for TileId {
 }
 
 #[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct TileTable<T>(pub(crate) Box<[T]>);
 
 impl<T> Deref for TileTable<T> {
@@ -65,7 +67,8 @@ impl<T, Data> IndexMut<&Tile<Data>> for TileTable<T> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Tile<Data> {
     pattern: Pattern<Data>,
     pub(crate) probability: f64,
@@ -81,19 +84,50 @@ impl<Data> Tile<Data> {
         }
     }
 
-    pub fn data(&self) -> &Box<[Data]> {
+    pub fn data(&self) -> &[Data] {
         self.pattern.data()
     }
+
+    pub fn id(&self) -> TileId {
+        self.id
+    }
+
+    /// The weight of the tile, how likely it is to be choosen relative to the
+    /// other tiles.
+    pub fn probability(&self) -> f64 {
+        self.probability
+    }
+
+    pub fn pattern(&self) -> &Pattern<Data> {
+        &self.pattern
+    }
+
+    /// Converts every element of the pattern, such as into a type which can
+    /// be serialized.
+    pub fn map<T>(self, f: impl FnMut(Data) -> T) -> Tile<T> {
+        Tile {
+            pattern: self.pattern.map(f),
+            probability: self.probability,
+            id: self.id,
+        }
+    }
 }
 
 impl<Data: PartialEq> Tile<Data> {
-    pub fn is_compatible(&self, b: &Tile<Data>, b_location: Location) -> bool {
-        self.pattern.is_compatible(&b.pattern, b_location)
+    pub fn is_compatible(
+        &self,
+        b: &Tile<Data>,
+        b_location: Location,
+        compatibility: Compatibility,
+    ) -> bool {
+        self.pattern
+            .is_compatible(&b.pattern, b_location, compatibility)
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct RemovedTile<'a, Data> {
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct RemovedTile {
     pub(crate) cell_index: usize,
-    pub(crate) tile: &'a Tile<Data>,
+    pub(crate) tile: TileId,
 }